@@ -0,0 +1,134 @@
+extern crate criterion;
+extern crate rlox;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rlox::chunk::Chunk;
+use rlox::compiler;
+use rlox::instructions::Instruction;
+use rlox::register_compiler;
+use rlox::register_instructions::RegisterChunk;
+use rlox::register_machine::RegisterMachine;
+use rlox::value::Value;
+use rlox::virtual_machine::{is_falsey, values_equal, VirtualMachine};
+
+/// Repeat an expression, joined with a binary operator
+fn repeat(expression: &str, operator: &str, repeats: usize) -> String {
+    vec![format!("({})", expression); repeats].join(operator)
+}
+
+/// Scripts that are dominated by instruction dispatch
+fn scripts(repeats: usize) -> Vec<(&'static str, String)> {
+    // Enough distinct constants to need multi-byte constant operands
+    let long_constants: Vec<String> = (0..repeats).map(|i| i.to_string()).collect();
+    vec![
+        ("arithmetic", repeat("(1 + 2) * 3 - 4 / 5", " + ", repeats)),
+        ("comparison", repeat("!(1 < 2) == (3 >= -4)", " == ", repeats)),
+        ("long_constants", long_constants.join(" + ")),
+    ]
+}
+
+fn run_chunk(chunk: &Chunk) {
+    VirtualMachine::new().interpret(chunk).unwrap();
+}
+
+fn bench_interpreter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter");
+    for (name, source) in scripts(2_000) {
+        // Compile without optimisation, which would fold the scripts to a single constant
+        let chunk = compiler::compile_unoptimised(&source).unwrap();
+        let instruction_count = chunk.instructions().count();
+        group.throughput(Throughput::Elements(instruction_count as u64));
+        group.bench_with_input(BenchmarkId::new(name, instruction_count), &chunk,
+            |b, chunk| b.iter(|| run_chunk(chunk)));
+    }
+    group.finish();
+}
+
+/// Run a chunk the way the interpreter loop did before it indexed the
+/// code directly, decoding every instruction with `Instruction::decode`
+/// and dispatching on the result. Only numbers are supported, which
+/// is all the scripts use.
+fn run_decoded(chunk: &Chunk) -> Value {
+    let mut stack: Vec<Value> = Vec::with_capacity(chunk.max_stack_depth);
+    let mut offset = 0;
+    loop {
+        let (instruction, length) = Instruction::decode(&chunk.code, offset).unwrap();
+        offset += length;
+        if let Some(index) = instruction.constant_index() {
+            stack.push(chunk.constants[index].clone());
+        }
+        match instruction {
+            Instruction::Return => return stack.pop().unwrap(),
+            Instruction::Constant(_) => {},
+            Instruction::Nil => stack.push(Value::nil()),
+            Instruction::True => stack.push(Value::bool(true)),
+            Instruction::False => stack.push(Value::bool(false)),
+            Instruction::Negate => {
+                let value = stack.pop().unwrap();
+                stack.push(Value::number(-value.as_number()));
+            },
+            Instruction::Not => {
+                let value = stack.pop().unwrap();
+                stack.push(Value::bool(is_falsey(value)));
+            },
+            Instruction::Equal | Instruction::NotEqual => {
+                let right = stack.pop().unwrap();
+                let left = stack.pop().unwrap();
+                let equal = values_equal(left, right);
+                stack.push(Value::bool(equal == (instruction == Instruction::Equal)));
+            },
+            _ => {
+                let right = stack.pop().unwrap().as_number();
+                let left = stack.pop().unwrap().as_number();
+                stack.push(match instruction {
+                    Instruction::Add | Instruction::AddConstant(_) => Value::number(left + right),
+                    Instruction::Subtract | Instruction::SubtractConstant(_) => Value::number(left - right),
+                    Instruction::Multiply => Value::number(left * right),
+                    Instruction::Divide => Value::number(left / right),
+                    Instruction::Greater | Instruction::GreaterConstant(_) => Value::bool(left > right),
+                    Instruction::Less | Instruction::LessConstant(_) => Value::bool(left < right),
+                    Instruction::GreaterEqual => Value::bool(left >= right),
+                    Instruction::LessEqual => Value::bool(left <= right),
+                    _ => unreachable!(),
+                });
+            },
+        }
+    }
+}
+
+/// Compare the interpreter loop with decoding every instruction,
+/// which is how it dispatched before indexing the code directly
+fn bench_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    for (name, source) in scripts(2_000) {
+        let chunk = compiler::compile_unoptimised(&source).unwrap();
+        let instruction_count = chunk.instructions().count();
+        group.throughput(Throughput::Elements(instruction_count as u64));
+        group.bench_with_input(BenchmarkId::new("decoded", name), &chunk,
+            |b, chunk| b.iter(|| run_decoded(chunk)));
+        group.bench_with_input(BenchmarkId::new("indexed", name), &chunk,
+            |b, chunk| b.iter(|| run_chunk(chunk)));
+    }
+    group.finish();
+}
+
+fn run_register_chunk(chunk: &RegisterChunk) {
+    RegisterMachine::new().interpret(chunk).unwrap();
+}
+
+/// The same scripts run with the register machine, for comparing the backends
+fn bench_register_interpreter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("register_interpreter");
+    for (name, source) in scripts(2_000) {
+        let chunk = compiler::compile_unoptimised(&source).unwrap();
+        let instruction_count = chunk.instructions().count();
+        let register_chunk = register_compiler::translate(&chunk).unwrap();
+        group.throughput(Throughput::Elements(instruction_count as u64));
+        group.bench_with_input(BenchmarkId::new(name, instruction_count), &register_chunk,
+            |b, chunk| b.iter(|| run_register_chunk(chunk)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_interpreter, bench_dispatch, bench_register_interpreter);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+extern crate criterion;
+extern crate rlox;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rlox::chunk::Chunk;
+use rlox::compiler;
+use rlox::superinstructions;
+use rlox::virtual_machine::VirtualMachine;
+
+/// Source that sums and compares many numbers, where every
+/// operation has a constant right hand operand
+fn arithmetic_source(term_count: usize) -> String {
+    let terms: Vec<String> = (0..term_count).map(|i| (i % 100).to_string()).collect();
+    format!("{} > {} - 1", terms.join(" + "), terms.join(" - "))
+}
+
+fn run_chunk(chunk: &Chunk) {
+    VirtualMachine::new().interpret(chunk).unwrap();
+}
+
+/// Names a benchmark by its term count and how many
+/// instructions the chunk took, so the saving is visible
+fn parameter(term_count: usize, chunk: &Chunk) -> String {
+    format!("{} terms/{} instructions", term_count, chunk.instructions().count())
+}
+
+fn bench_superinstructions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("superinstructions");
+    for &term_count in &[1_000, 10_000] {
+        // Compile without constant folding, which would evaluate the whole script
+        let unfused = compiler::compile_unoptimised(&arithmetic_source(term_count)).unwrap();
+        let fused = superinstructions::fuse(&unfused).unwrap();
+
+        group.throughput(Throughput::Elements(2 * term_count as u64));
+        group.bench_with_input(BenchmarkId::new("unfused", parameter(term_count, &unfused)), &unfused,
+            |b, chunk| b.iter(|| run_chunk(chunk)));
+        group.bench_with_input(BenchmarkId::new("fused", parameter(term_count, &fused)), &fused,
+            |b, chunk| b.iter(|| run_chunk(chunk)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_superinstructions);
+criterion_main!(benches);
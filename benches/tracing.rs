@@ -0,0 +1,45 @@
+extern crate criterion;
+extern crate rlox;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rlox::chunk::Chunk;
+use rlox::instructions::Instruction;
+use rlox::trace::{TraceFormat, TraceOptions, Tracer};
+use rlox::virtual_machine::VirtualMachine;
+use std::io;
+
+/// Build a chunk that executes the given number of instructions,
+/// with every instruction on its own line so the line table has
+/// one run per instruction
+fn long_chunk(instruction_count: usize) -> Chunk {
+    let mut chunk = Chunk::new();
+    chunk.write_instruction(Instruction::Nil, 1);
+    for line in 2..instruction_count {
+        chunk.write_instruction(Instruction::Not, line);
+    }
+    chunk.write_instruction(Instruction::Return, instruction_count);
+    chunk
+}
+
+fn trace_chunk(chunk: &Chunk, format: TraceFormat) {
+    let mut vm = VirtualMachine::new();
+    vm.set_tracer(Some(Tracer::new(Box::new(io::sink()), TraceOptions::new(format))));
+    vm.interpret(chunk).unwrap();
+}
+
+fn bench_tracing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tracing");
+    group.sample_size(10);
+    for &instruction_count in &[10_000, 50_000, 100_000] {
+        let chunk = long_chunk(instruction_count);
+        group.throughput(Throughput::Elements(instruction_count as u64));
+        group.bench_with_input(BenchmarkId::new("text", instruction_count), &chunk,
+            |b, chunk| b.iter(|| trace_chunk(chunk, TraceFormat::Text)));
+        group.bench_with_input(BenchmarkId::new("json", instruction_count), &chunk,
+            |b, chunk| b.iter(|| trace_chunk(chunk, TraceFormat::JsonLines)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_tracing);
+criterion_main!(benches);
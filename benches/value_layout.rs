@@ -0,0 +1,64 @@
+extern crate criterion;
+extern crate rlox;
+
+use std::mem;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rlox::compiler;
+use rlox::value::Value;
+use rlox::virtual_machine::VirtualMachine;
+
+const VALUE_COUNT: usize = 10_000;
+
+/// Names the layout being measured, so results from
+/// both builds are kept side by side
+fn layout() -> String {
+    let name = if cfg!(feature = "nan-boxing") { "nan_boxed" } else { "enum" };
+    format!("{}/{} bytes", name, mem::size_of::<Value>())
+}
+
+/// A mix of every type of value
+fn values() -> Vec<Value> {
+    (0..VALUE_COUNT).map(|i| match i % 4 {
+        0 => Value::nil(),
+        1 => Value::bool(i % 3 == 0),
+        2 => Value::string(i.to_string()),
+        _ => Value::number(i as f64),
+    }).collect()
+}
+
+fn sum_numbers(values: &[Value]) -> f64 {
+    values.iter()
+        .filter(|value| value.is_number())
+        .map(|value| value.as_number())
+        .sum()
+}
+
+/// Compare the enum and NaN-boxed `Value` layouts. Only one layout can
+/// be compiled in at a time, so run this both with and without the
+/// feature and compare the two sets of results:
+///
+/// ```text
+/// cargo bench --bench value_layout
+/// cargo bench --bench value_layout --features nan-boxing
+/// ```
+fn bench_value_layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("value_layout");
+    group.throughput(Throughput::Elements(VALUE_COUNT as u64));
+
+    let values = values();
+    group.bench_with_input(BenchmarkId::new("clone_and_drop", layout()), &values,
+        |b, values| b.iter(|| values.clone()));
+    group.bench_with_input(BenchmarkId::new("check_types", layout()), &values,
+        |b, values| b.iter(|| sum_numbers(values)));
+
+    // Compile without optimisation, which would fold the script to a single constant
+    let terms: Vec<String> = (0..VALUE_COUNT).map(|i| format!("({} * 2 > 1) == true", i)).collect();
+    let chunk = compiler::compile_unoptimised(&terms.join(" == ")).unwrap();
+    group.bench_with_input(BenchmarkId::new("interpret", layout()), &chunk,
+        |b, chunk| b.iter(|| VirtualMachine::new().interpret(chunk).unwrap()));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_value_layout);
+criterion_main!(benches);
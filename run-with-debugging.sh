@@ -1,3 +1,3 @@
 #!/bin/bash
 
-cargo run --features "debug-print-code debug-trace-execution"
+cargo run --features "debug-print-code" -- --trace
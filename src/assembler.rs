@@ -0,0 +1,380 @@
+use fnv::FnvHashMap;
+
+use ::chunk::Chunk;
+use ::errors::{InterpretError, InterpretResult};
+use ::instructions::*;
+use ::value::Value;
+
+/// How far past the end of the constants table an index may be. Skipped
+/// constants are filled with nil, and this keeps the table proportional
+/// to the size of the listing rather than to the largest index in it.
+const MAX_CONSTANT_GAP: usize = 256;
+
+/// A chunk assembled from a textual listing, along with the
+/// offsets of any labels defined in the listing
+pub struct Assembly {
+    pub chunk: Chunk,
+    /// Offsets of labels within the code
+    pub labels: FnvHashMap<String, usize>,
+}
+
+/// Assemble a chunk from a listing in the format written by the disassembler,
+/// for example:
+///
+/// ```text
+/// == code ==
+/// 0000    1 OpCode::Constant    0 'Number(1)'
+/// 0002    | OpCode::Negate
+/// start:
+///         2 OpCode::Constant 'Number(2)'
+///           OpCode::Add
+///           OpCode::Return
+/// ```
+///
+/// Each instruction may be preceded by an offset, which is checked against the
+/// actual offset, and a line number, where `|` or no line number means the
+/// same line as the previous instruction. Constant instructions take an index
+/// into the constants table, a value, or both. Lines starting with `;` are
+/// comments and `name:` defines a label.
+pub fn assemble(source: &str) -> InterpretResult<Assembly> {
+    let mut assembler = Assembler {
+        chunk: Chunk::new(),
+        constants: Vec::new(),
+        labels: FnvHashMap::default(),
+        line: 1,
+    };
+    for (line_index, line) in join_string_lines(source).iter().enumerate() {
+        assembler.assemble_line(line)
+            .map_err(|message| InterpretError::CompileError(
+                format!("Assembly error on line {}: {}", line_index + 1, message)))?;
+    }
+    assembler.finish()
+}
+
+struct Assembler {
+    chunk: Chunk,
+    /// Constants may be defined in any order, so aren't
+    /// added to the chunk until assembly is finished
+    constants: Vec<Option<Value>>,
+    labels: FnvHashMap<String, usize>,
+    line: usize,
+}
+
+impl Assembler {
+    fn assemble_line(&mut self, line: &str) -> Result<(), String> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || is_comment(trimmed) {
+            return Ok(());
+        }
+        if let Some(label) = trimmed.strip_suffix(':') {
+            return self.define_label(label);
+        }
+
+        // Split off the quoted constant value, which may contain spaces
+        let (instruction, value) = match (line.find('\''), line.rfind('\'')) {
+            (Some(start), Some(end)) if end > start => {
+                let value = parse_value(&line[start + 1..end])
+                    .ok_or_else(|| format!("Invalid constant value '{}'", &line[start + 1..end]))?;
+                (&line[..start], Some(value))
+            },
+            (Some(_), _) => return Err("Unterminated constant value".to_string()),
+            _ => (line, None),
+        };
+
+        let tokens: Vec<&str> = instruction.split_whitespace().collect();
+        let op_code_position = tokens.iter()
+            .position(|token| token.starts_with("OpCode::"))
+            .ok_or_else(|| "Expected an op code".to_string())?;
+        self.parse_prefix(&tokens[..op_code_position])?;
+
+        let name = &tokens[op_code_position]["OpCode::".len()..];
+        let op_code = OpCode::from_name(name)
+            .ok_or_else(|| format!("Unknown op code '{}'", name))?;
+        let operands = &tokens[op_code_position + 1..];
+
+        match op_code {
+            OpCode::Constant | OpCode::AddConstant | OpCode::SubtractConstant
+                | OpCode::LessConstant | OpCode::GreaterConstant => {
+                let index = self.constant_index(operands, value)?;
+                self.chunk.write_instruction(Instruction::with_constant(op_code, index as u32).unwrap(), self.line);
+            },
+            _ => {
+                if !operands.is_empty() || value.is_some() {
+                    return Err(format!("{:?} doesn't take any operands", op_code));
+                }
+                self.chunk.write_instruction(SimpleInstruction::new(op_code), self.line);
+            },
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &str) -> Result<(), String> {
+        let valid_name = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(format!("Invalid label name '{}'", name));
+        }
+        let offset = self.chunk.code.len();
+        if self.labels.insert(name.to_string(), offset).is_some() {
+            return Err(format!("Label '{}' is already defined", name));
+        }
+        Ok(())
+    }
+
+    /// Parse the optional offset and line number before an op code
+    fn parse_prefix(&mut self, tokens: &[&str]) -> Result<(), String> {
+        let line = match tokens {
+            [] => None,
+            [line] => Some(line),
+            [offset, line] => {
+                let offset: usize = offset.parse()
+                    .map_err(|_| format!("Invalid offset '{}'", offset))?;
+                if offset != self.chunk.code.len() {
+                    return Err(format!("Expected offset {} but instruction is at {}", offset, self.chunk.code.len()));
+                }
+                Some(line)
+            },
+            _ => return Err("Unexpected tokens before op code".to_string()),
+        };
+        match line {
+            Some(&"|") | None => {},
+            Some(line) => {
+                self.line = line.parse().map_err(|_| format!("Invalid line number '{}'", line))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the index of a constant from an instruction's operands,
+    /// recording its value if given
+    fn constant_index(&mut self, operands: &[&str], value: Option<Value>) -> Result<usize, String> {
+        let index = match operands {
+            [] => None,
+            [index] => Some(index.parse::<u32>().map_err(|_| format!("Invalid constant index '{}'", index))? as usize),
+            _ => return Err("Too many operands".to_string()),
+        };
+        if let Some(index) = index {
+            if index > self.constants.len() + MAX_CONSTANT_GAP {
+                return Err(format!("Constant index {} is too far past the {} constants defined so far",
+                    index, self.constants.len()));
+            }
+        }
+        match (index, value) {
+            (Some(index), Some(value)) => {
+                if index >= self.constants.len() {
+                    self.constants.resize(index + 1, None);
+                }
+                match &self.constants[index] {
+                    Some(existing) if existing.to_string() != value.to_string() => {
+                        return Err(format!("Constant {} is already defined as {}", index, existing));
+                    },
+                    _ => {},
+                }
+                self.constants[index] = Some(value);
+                Ok(index)
+            },
+            (Some(index), None) => {
+                if index >= self.constants.len() {
+                    self.constants.resize(index + 1, None);
+                }
+                Ok(index)
+            },
+            (None, Some(value)) => {
+                self.constants.push(Some(value));
+                Ok(self.constants.len() - 1)
+            },
+            (None, None) => Err("Expected a constant index or value".to_string()),
+        }
+    }
+
+    fn finish(mut self) -> InterpretResult<Assembly> {
+        // Constants that are referenced by an instruction must have a value,
+        // but any others can be left as nil.
+        let mut referenced = vec![false; self.constants.len()];
+        for result in self.chunk.instructions() {
+            // Instructions were written by the assembler so must be valid
+            let (_, instruction, _) = result.unwrap();
+            if let Some(index) = instruction.constant_index() {
+                referenced[index] = true;
+            }
+        }
+        for (index, constant) in self.constants.into_iter().enumerate() {
+            match constant {
+                Some(value) => self.chunk.constants.push(value),
+                None if referenced[index] => {
+                    return Err(InterpretError::CompileError(format!("Constant {} has no value", index)));
+                },
+                None => self.chunk.constants.push(Value::nil()),
+            }
+        }
+        self.chunk.max_stack_depth = self.chunk.compute_max_stack_depth();
+        Ok(Assembly {
+            chunk: self.chunk,
+            labels: self.labels,
+        })
+    }
+}
+
+/// Parse a value as formatted for display
+fn parse_value(text: &str) -> Option<Value> {
+    if text == "Nil" {
+        Some(Value::nil())
+    } else if text == "Bool(true)" {
+        Some(Value::bool(true))
+    } else if text == "Bool(false)" {
+        Some(Value::bool(false))
+    } else if text.starts_with("Number(") && text.ends_with(')') {
+        text["Number(".len()..text.len() - 1].parse().ok().map(Value::number)
+    } else if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        let string = text[1..text.len() - 1].to_string();
+        Some(Value::string(string))
+    } else {
+        None
+    }
+}
+
+/// Whether a line is a comment or a disassembly header, which are ignored
+fn is_comment(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.starts_with(';') || trimmed.starts_with("==")
+}
+
+/// Split the source into lines, but keep newlines that are within
+/// string constants. Quotes in comments don't start a string.
+fn join_string_lines(source: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for line in source.lines() {
+        if current.is_empty() && is_comment(line) {
+            lines.push(line.to_string());
+            continue;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        if current.matches('"').count().is_multiple_of(2) {
+            lines.push(current);
+            current = String::new();
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+    use ::debug;
+
+    fn disassemble(chunk: &Chunk) -> String {
+        let mut output = Vec::new();
+        debug::disassemble_chunk(&mut output, chunk, "test").unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn assert_round_trip(chunk: &Chunk) {
+        let listing = disassemble(chunk);
+        let assembly = assemble(&listing).unwrap();
+        assert_eq!(assembly.chunk.code, chunk.code, "Code differs after round trip of:\n{}", listing);
+        let lines: Vec<&usize> = assembly.chunk.lines.into_iter().collect();
+        let expected_lines: Vec<&usize> = chunk.lines.into_iter().collect();
+        assert_eq!(lines, expected_lines);
+        assert_eq!(disassemble(&assembly.chunk), listing);
+    }
+
+    #[test]
+    fn test_round_trip_compiled_chunks() {
+        let sources = vec![
+            "1",
+            "-(1.5 + 2) * 3 / 4 - 5",
+            "!(1 < 2) == (3 >= 4)\n!= (nil == false)",
+            "\"multi\nline\" + \"string's\"",
+            "0.1 + 12345678.875 * 3",
+        ];
+        for source in sources {
+            assert_round_trip(&compiler::compile_unoptimised(source).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_constant_long() {
+        let terms: Vec<String> = (0..300).map(|i| i.to_string()).collect();
+        let chunk = compiler::compile_unoptimised(&terms.join(" +\n")).unwrap();
+        assert_round_trip(&chunk);
+    }
+
+    #[test]
+    fn test_assemble_hand_written_listing() {
+        let assembly = assemble("\
+; Negate a number then add it to another
+        OpCode::Constant 'Number(1)'
+        OpCode::Negate
+start:
+    2   OpCode::Constant    'Number(2.5)'
+        OpCode::Add
+    3   OpCode::Return
+").unwrap();
+
+        let chunk = &assembly.chunk;
+        assert_eq!(chunk.code, vec![
+            OpCode::Constant.as_byte(), 0,
+            OpCode::Negate.as_byte(),
+            OpCode::Constant.as_byte(), 1,
+            OpCode::Add.as_byte(),
+            OpCode::Return.as_byte(),
+        ]);
+        let constants: Vec<String> = chunk.constants.iter().map(|c| c.to_string()).collect();
+        assert_eq!(constants, vec!["Number(1)", "Number(2.5)"]);
+        let lines: Vec<&usize> = chunk.lines.into_iter().collect();
+        assert_eq!(lines, vec![&1, &1, &1, &2, &2, &2, &3]);
+        assert_eq!(assembly.labels.get("start"), Some(&3));
+    }
+
+    #[test]
+    fn test_quotes_in_comments() {
+        let assembly = assemble("\
+; Push \"hello
+1 OpCode::Constant '\"a
+b\"'
+; world\" and return it
+OpCode::Return
+").unwrap();
+
+        assert_eq!(assembly.chunk.code, vec![OpCode::Constant.as_byte(), 0, OpCode::Return.as_byte()]);
+        assert_eq!(assembly.chunk.constants[0].as_string(), "a\nb");
+    }
+
+    #[test]
+    fn test_constant_gaps_are_nil() {
+        let assembly = assemble("OpCode::Constant 2 'Bool(true)'\nOpCode::Constant 200 'Nil'").unwrap();
+        let constants = &assembly.chunk.constants;
+        assert_eq!(constants.len(), 201);
+        assert!(constants[0].is_nil() && constants[2].as_bool() && constants[200].is_nil());
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let invalid_listings = vec![
+            "OpCode::Bogus",
+            "OpCode::Add 1",
+            "OpCode::Constant",
+            "OpCode::Constant 0",
+            "OpCode::Constant 'Number(x)'",
+            "OpCode::Constant 4294967296 'Nil'",
+            "OpCode::Constant 4000000000 'Nil'",
+            "OpCode::Constant 0 'Nil'\nOpCode::Constant 258",
+            "0001 1 OpCode::Nil",
+            "x 1 OpCode::Nil",
+            "1 OpCode::Nil\n1 OpCode::Constant 0 'Nil'\nOpCode::Constant 0 'Bool(true)'",
+            "a:\na:",
+            "1a:",
+        ];
+        for listing in invalid_listings {
+            assert!(assemble(listing).is_err(), "Expected error assembling '{}'", listing);
+        }
+    }
+}
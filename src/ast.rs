@@ -0,0 +1,85 @@
+use std::fmt;
+
+/// An expression parsed from source code by `parser::parse`
+#[derive(Debug,Clone,PartialEq)]
+pub enum Expr {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Unary {
+        operator: UnaryOperator,
+        operand: Box<Expr>,
+        /// Line of the operator
+        line: usize,
+    },
+    Binary {
+        operator: BinaryOperator,
+        left: Box<Expr>,
+        right: Box<Expr>,
+        /// Line of the operator
+        line: usize,
+    },
+}
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl UnaryOperator {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOperator::Negate => "-",
+            UnaryOperator::Not => "!",
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+        }
+    }
+}
+
+/// Formats an expression as an s-expression, with every
+/// operation in parentheses to show how it was grouped
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Nil => write!(f, "nil"),
+            Expr::Bool(value) => write!(f, "{}", value),
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::String(value) => write!(f, "\"{}\"", value),
+            Expr::Unary { operator, operand, .. } => write!(f, "({} {})", operator.symbol(), operand),
+            Expr::Binary { operator, left, right, .. } =>
+                write!(f, "({} {} {})", operator.symbol(), left, right),
+        }
+    }
+}
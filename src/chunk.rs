@@ -1,13 +1,93 @@
-use std;
+use fnv::FnvHashMap;
 use ::errors::{InterpretResult, InterpretError};
 use ::instructions::*;
-use ::run_length_encoding::RunLengthEncoded;
+use ::run_length_encoding::{RunLengthEncoded, RunLengthIterator};
+use ::object::LoxObject;
 use ::value::Value;
 
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: Vec<Value>,
     pub lines: RunLengthEncoded<usize>,
+    /// The most values on the stack at any point while executing
+    /// the chunk, so the stack can be allocated up front
+    pub max_stack_depth: usize,
+    /// Indices of constants added with write_constant, for reusing
+    /// an existing constant when the same literal appears again
+    constant_indices: FnvHashMap<ConstantKey, usize>,
+}
+
+/// Identifies constant values that can share a constant pool entry.
+/// Numbers are compared bitwise, so that -0.0 and 0.0 stay distinct
+/// and NaN can be reused.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(String),
+}
+
+impl ConstantKey {
+    fn new(value: &Value) -> ConstantKey {
+        if value.is_nil() {
+            ConstantKey::Nil
+        } else if value.is_bool() {
+            ConstantKey::Bool(value.as_bool())
+        } else if value.is_number() {
+            ConstantKey::Number(value.as_number().to_bits())
+        } else {
+            match *value.as_object() {
+                LoxObject::String(ref s) => ConstantKey::String(s.clone()),
+            }
+        }
+    }
+}
+
+/// Iterates over the decoded instructions in a chunk, along with their
+/// offsets and line numbers. After an unknown op code, decoding continues
+/// from the next byte, but a truncated instruction ends the iteration.
+pub struct Instructions<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+    lines: RunLengthIterator<'a, usize>,
+    lines_read: usize,
+    line: Option<usize>,
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = InterpretResult<(usize, Instruction, Option<usize>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.chunk.code.len() {
+            return None;
+        }
+        let offset = self.offset;
+        while self.lines_read <= offset {
+            self.line = self.lines.next().cloned();
+            self.lines_read += 1;
+        }
+        match Instruction::decode(&self.chunk.code, offset) {
+            Ok((instruction, length)) => {
+                self.offset += length;
+                Some(Ok((offset, instruction, self.line)))
+            },
+            Err(err @ InterpretError::UnknownOpCode { .. }) => {
+                self.offset += 1;
+                Some(Err(err))
+            },
+            Err(err) => {
+                self.offset = self.chunk.code.len();
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Chunk {
+        Chunk::new()
+    }
 }
 
 impl Chunk {
@@ -16,6 +96,8 @@ impl Chunk {
             code: Vec::new(),
             constants: Vec::new(),
             lines: RunLengthEncoded::new(),
+            max_stack_depth: 0,
+            constant_indices: FnvHashMap::default(),
         }
     }
 
@@ -26,34 +108,76 @@ impl Chunk {
         self.lines.push_run(line, new_code_len - initial_code_len);
     }
 
-    pub fn write_constant(&mut self, value: Value, line: usize) -> InterpretResult<()> {
-        self.constants.push(value);
-        let constant_index = self.constants.len() - 1;
-        // Once we have over 256 constants, we need to start
-        // saving constants using a constant long instruction:
-        if constant_index <= std::u8::MAX as usize
-        {
-            self.write_instruction(ConstantInstruction::new(constant_index as u8), line);
-            Ok(())
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            chunk: self,
+            offset: 0,
+            lines: self.lines.into_iter(),
+            lines_read: 0,
+            line: None,
         }
-        else if constant_index <= std::u32::MAX as usize  {
-            self.write_instruction(ConstantLongInstruction::new(constant_index as u32), line);
+    }
+
+    /// Decode all the instructions, for comparing against in tests
+    #[cfg(test)]
+    pub fn decoded_instructions(&self) -> Vec<Instruction> {
+        self.instructions().map(|result| result.unwrap().1).collect()
+    }
+
+    pub fn write_constant(&mut self, value: Value, line: usize) -> InterpretResult<()> {
+        let constant_index = self.add_constant(value);
+        // The index is written as a varint, so grows a byte at a time
+        if constant_index <= u32::MAX as usize {
+            self.write_instruction(Instruction::Constant(constant_index as u32), line);
             Ok(())
         }
         else {
             Err(InterpretError::CompileError("Too many constants to store".to_string()))
         }
     }
+
+    /// Find the maximum stack depth reached while executing the chunk,
+    /// by following the stack effect of each instruction. Malformed
+    /// instructions are skipped, as they are reported by the verifier.
+    pub fn compute_max_stack_depth(&self) -> usize {
+        let mut depth: usize = 0;
+        let mut max_depth = 0;
+        for (_, instruction, _) in self.instructions().filter_map(|result| result.ok()) {
+            let (pops, pushes) = instruction.op_code().stack_effect();
+            depth = depth.saturating_sub(pops) + pushes;
+            max_depth = max_depth.max(depth);
+        }
+        max_depth
+    }
+
+    /// Add a value to the constant pool, returning the index of an
+    /// existing equal constant if there is one
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let key = ConstantKey::new(&value);
+        if let Some(&index) = self.constant_indices.get(&key) {
+            // Constants may also have been modified directly, so check
+            // the pool still holds the same value
+            if self.constants.get(index).is_some_and(|constant| ConstantKey::new(constant) == key) {
+                return index;
+            }
+        }
+        self.constants.push(value);
+        let index = self.constants.len() - 1;
+        self.constant_indices.insert(key, index);
+        index
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io::{Cursor, Read, Write};
-    use byteorder::{ReadBytesExt, LittleEndian};
+    use std::io::Write;
     use ::chunk::Chunk;
+    use ::errors::{InterpretError, InterpretResult};
     use ::value::Value;
+    use ::instructions::Instruction;
     use ::instructions::InstructionWrite;
     use ::instructions::OpCode;
+    use ::instructions::read_varint;
 
     struct TestInstruction
     {
@@ -62,7 +186,7 @@ mod test {
 
     impl InstructionWrite for TestInstruction {
         fn write<W: Write>(&self, writer: &mut W) {
-            writer.write(&[self.value]).unwrap();
+            writer.write_all(&[self.value]).unwrap();
         }
     }
 
@@ -101,20 +225,13 @@ mod test {
     #[test]
     fn test_write_constant() {
         let mut chunk = Chunk::new();
-        let value = Value::NumberValue(42.0);
+        let value = Value::number(42.0);
 
         let result = chunk.write_constant(value, 123);
         assert!(result.is_ok(), "Expected ok result when writing constant");
 
         assert_eq!(chunk.constants.len(), 1);
-        match chunk.constants[0] {
-            Value::NumberValue(val) => {
-                assert_eq!(val, 42.0);
-            },
-            _ => {
-                assert!(false, "Expected NumberValue");
-            }
-        }
+        assert_eq!(chunk.constants[0].as_number(), 42.0);
 
         assert_eq!(chunk.code.len(), 2);
         assert_eq!(chunk.code[0], OpCode::Constant.as_byte());
@@ -126,34 +243,107 @@ mod test {
         assert_eq!(lines[1], &123);
     }
 
+    #[test]
+    fn test_repeated_constants_are_deduplicated() {
+        let mut chunk = Chunk::new();
+        for _ in 0..257 {
+            chunk.write_constant(Value::number(1.0), 1).unwrap();
+            chunk.write_constant(Value::string("a".to_string()), 1).unwrap();
+        }
+
+        assert_eq!(chunk.constants.len(), 2);
+        let instructions: Vec<Instruction> = chunk.instructions()
+            .map(|result| result.unwrap().1)
+            .collect();
+        assert_eq!(instructions.len(), 514);
+        for (i, instruction) in instructions.iter().enumerate() {
+            assert_eq!(*instruction, Instruction::Constant((i % 2) as u32));
+        }
+    }
+
+    #[test]
+    fn test_distinct_numbers_are_not_deduplicated() {
+        let mut chunk = Chunk::new();
+        let values = [0.0, -0.0, f64::NAN, f64::NAN, 1.0, 0.0];
+        let indices: Vec<usize> = values.iter()
+            .map(|value| chunk.add_constant(Value::number(*value)))
+            .collect();
+
+        assert_eq!(indices, vec![0, 1, 2, 2, 3, 0]);
+        assert_eq!(chunk.add_constant(Value::bool(false)), 4);
+        assert_eq!(chunk.add_constant(Value::nil()), 5);
+        assert_eq!(chunk.add_constant(Value::bool(false)), 4);
+    }
+
+    #[test]
+    fn test_compute_max_stack_depth() {
+        let chunk = ::compiler::compile_unoptimised("1 + (2 * (3 - -4)) + 5").unwrap();
+        assert_eq!(chunk.compute_max_stack_depth(), 4);
+        assert_eq!(Chunk::new().compute_max_stack_depth(), 0);
+    }
+
+    #[test]
+    fn test_iterate_instructions() {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::number(1.0), 1).unwrap();
+        chunk.write_instruction(Instruction::Negate, 2);
+        chunk.write_instruction(Instruction::Constant(300), 2);
+        chunk.write_instruction(Instruction::Return, 3);
+
+        let instructions: Vec<(usize, Instruction, Option<usize>)> = chunk.instructions()
+            .map(|result| result.unwrap())
+            .collect();
+        assert_eq!(instructions, vec![
+            (0, Instruction::Constant(0), Some(1)),
+            (2, Instruction::Negate, Some(2)),
+            (3, Instruction::Constant(300), Some(2)),
+            (6, Instruction::Return, Some(3)),
+        ]);
+    }
+
+    #[test]
+    fn test_iterate_instructions_with_errors() {
+        let mut chunk = Chunk::new();
+        chunk.write_instruction(Instruction::Nil, 1);
+        chunk.write_instruction(Instruction::Return, 1);
+        chunk.write_instruction(Instruction::Constant(0), 1);
+        chunk.code[1] = 255;
+        chunk.code.pop();
+
+        let results: Vec<InterpretResult<(usize, Instruction, Option<usize>)>> = chunk.instructions().collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &(0, Instruction::Nil, Some(1)));
+        match results[1] {
+            Err(InterpretError::UnknownOpCode { offset: 1, byte: 255 }) => {},
+            _ => assert!(false, "Expected unknown op code"),
+        }
+        match results[2] {
+            Err(InterpretError::TruncatedInstruction { offset: 2 }) => {},
+            _ => assert!(false, "Expected truncated instruction"),
+        }
+    }
+
     #[test]
     fn test_write_more_than_256_constants() {
         let mut chunk = Chunk::new();
 
         for i in 0..257 {
-            let value = Value::NumberValue(i as f64);
+            let value = Value::number(i as f64);
             let result = chunk.write_constant(value, i);
             assert!(result.is_ok(), "Expected ok result when writing constant");
         }
 
         assert_eq!(chunk.constants.len(), 257);
         for i in 0..257 {
-            match chunk.constants[i] {
-                Value::NumberValue(val) => {
-                    assert_eq!(val, i as f64);
-                },
-                _ => {
-                    assert!(false, "Expected NumberValue");
-                }
-            }
+            assert_eq!(chunk.constants[i].as_number(), i as f64);
         }
 
-        // 2 bytes for first 256 values then 1 byte for op code + 4 for index
-        assert_eq!(chunk.code.len(), 256 * 2 + 1 + 4);
-        assert_eq!(chunk.code[256 * 2], OpCode::ConstantLong.as_byte());
-        let mut cursor = Cursor::new(&chunk.code);
-        cursor.set_position(256 * 2 + 1);
-        let index = cursor.read_u32::<LittleEndian>().unwrap();
+        // 2 bytes for the first 128 values, then 1 byte for the
+        // op code and 2 for the index
+        assert_eq!(chunk.code.len(), 128 * 2 + 129 * 3);
+        assert_eq!(chunk.code[128 * 2 + 128 * 3], OpCode::Constant.as_byte());
+        let mut operand = &chunk.code[128 * 2 + 128 * 3 + 1..];
+        let index = read_varint(&mut operand).unwrap();
         assert_eq!(index, 256u32);  // Index into constant array
     }
 }
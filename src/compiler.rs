@@ -1,5 +1,6 @@
 use std::error::Error;
-use std::rc::Rc;
+#[cfg(feature="debug-print-code")]
+use std::io;
 use num_traits::FromPrimitive;
 use num_traits::ToPrimitive;
 
@@ -7,12 +8,47 @@ use chunk::Chunk;
 #[cfg(feature="debug-print-code")]
 use debug;
 use errors::{InterpretError, InterpretResult};
+use folding;
 use instructions::*;
-use object::LoxObject;
+use peephole;
 use scanner::{Scanner, Token, TokenType};
+use superinstructions;
 use value::Value;
 
+/// How much optimisation to apply when compiling
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum OptimisationLevel {
+    /// Keep the instructions exactly as emitted by the parser (-O0)
+    None,
+    /// Fold constants, apply peephole optimisations and
+    /// use superinstructions (-O1)
+    Basic,
+}
+
 pub fn compile(source: &str) -> InterpretResult<Chunk>
+{
+    compile_with_optimisation(source, OptimisationLevel::Basic)
+}
+
+pub fn compile_with_optimisation(source: &str, level: OptimisationLevel) -> InterpretResult<Chunk>
+{
+    let mut chunk = compile_unoptimised(source)?;
+    if level == OptimisationLevel::Basic {
+        chunk = folding::fold_constants(&chunk)?;
+        chunk = peephole::optimise(&chunk)?;
+        chunk = superinstructions::fuse(&chunk)?;
+        chunk.max_stack_depth = chunk.compute_max_stack_depth();
+    }
+    #[cfg(feature="debug-print-code")]
+    {
+        debug::disassemble_chunk(&mut io::stdout(), &chunk, "code")?;
+    }
+    Ok(chunk)
+}
+
+/// Compile source code to a chunk containing exactly
+/// the instructions emitted by the parser
+pub fn compile_unoptimised(source: &str) -> InterpretResult<Chunk>
 {
     let mut scanner = Scanner::new(&source);
     let compiler = Compiler::new(&mut scanner);
@@ -79,11 +115,12 @@ impl <'a, 's> Compiler<'a, 's> {
         if self.parser.had_error {
             #[cfg(feature="debug-print-code")]
             {
-                debug::disassemble_chunk(&self.chunk, "code");
+                debug::disassemble_chunk(&mut io::stdout(), &self.chunk, "code")?;
             }
             Err(InterpretError::CompileError("Compilation error occurred".to_string()))
         } else {
             self.end_compiler();
+            self.chunk.max_stack_depth = self.chunk.compute_max_stack_depth();
             Ok(self.chunk)
         }
     }
@@ -112,12 +149,6 @@ impl <'a, 's> Compiler<'a, 's> {
 
     fn end_compiler(&mut self) {
         self.write_op_code(OpCode::Return);
-        #[cfg(feature="debug-print-code")]
-        {
-            if !self.parser.had_error {
-                debug::disassemble_chunk(&self.chunk, "code");
-            }
-        }
     }
 
     fn error(&mut self, message: &str) {
@@ -179,14 +210,6 @@ impl <'a, 's> Compiler<'a, 's> {
         self.chunk.write_instruction(instruction, line_no);
     }
 
-    fn write_op_codes(&mut self, op_codes: &[OpCode]) {
-        let line_no = self.parser.previous.as_ref().map_or(0, |t| t.line);
-        for op_code in op_codes {
-            let instruction = SimpleInstruction::new(op_code.clone());
-            self.chunk.write_instruction(instruction, line_no);
-        }
-    }
-
     fn emit_constant(&mut self, value: Value) {
         let line_no = self.parser.previous.as_ref().map_or(0, |t| t.line);
         if let Err(err) = self.chunk.write_constant(value, line_no) {
@@ -208,12 +231,12 @@ impl <'a, 's> Compiler<'a, 's> {
 
         // Emit the operator instruction
         match operator_type {
-            TokenType::BangEqual => self.write_op_codes(&[OpCode::Equal, OpCode::Not]),
+            TokenType::BangEqual => self.write_op_code(OpCode::NotEqual),
             TokenType::EqualEqual => self.write_op_code(OpCode::Equal),
             TokenType::Greater => self.write_op_code(OpCode::Greater),
-            TokenType::GreaterEqual => self.write_op_codes(&[OpCode::Less, OpCode::Not]),
+            TokenType::GreaterEqual => self.write_op_code(OpCode::GreaterEqual),
             TokenType::Less => self.write_op_code(OpCode::Less),
-            TokenType::LessEqual => self.write_op_codes(&[OpCode::Greater, OpCode::Not]),
+            TokenType::LessEqual => self.write_op_code(OpCode::LessEqual),
             TokenType::Plus => self.write_op_code(OpCode::Add),
             TokenType::Minus => self.write_op_code(OpCode::Subtract),
             TokenType::Star => self.write_op_code(OpCode::Multiply),
@@ -250,7 +273,7 @@ impl <'a, 's> Compiler<'a, 's> {
     fn string(&mut self) {
         let token_source = self.parser.previous.as_ref().unwrap().source;
         let string_value = token_source[1..token_source.len() - 1].to_string();
-        let value = Value::ObjValue(Rc::new(LoxObject::String(string_value)));
+        let value = Value::string(string_value);
         self.emit_constant(value);
     }
 
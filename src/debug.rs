@@ -1,90 +1,207 @@
-use std::io::{Cursor, Read};
-#[cfg(any(feature="debug-trace-execution"))]
-use std::io::{Seek, SeekFrom};
+use std::io;
+use std::io::Write;
 
 use ::chunk::Chunk;
+use ::errors::InterpretError;
 use ::instructions::*;
+use ::register_instructions::RegisterChunk;
 
-#[cfg(any(feature="debug-print-code"))]
-pub fn disassemble_chunk(chunk: &Chunk, name: &str) {
-    println!("== {} ==", name);
-
-    let mut reader = Cursor::new(&chunk.code);
-    let mut lines = chunk.lines.into_iter();
-    let mut line_index = 0;
-    let mut prev_line;
-    let mut line = None;
-
-    while reader.position() < (chunk.code.len() as u64) {
-        prev_line = line;
-        while line_index <= reader.position() {
-            line = lines.next();
-            line_index += 1;
+pub fn disassemble_chunk<W: Write>(writer: &mut W, chunk: &Chunk, name: &str) -> io::Result<()> {
+    writeln!(writer, "== {} ==", name)?;
+
+    let mut prev_line = None;
+    for result in chunk.instructions() {
+        match result {
+            Ok((offset, instruction, line)) => {
+                write_prefix(writer, offset, prev_line, line)?;
+                write_instruction(writer, chunk, &instruction)?;
+                prev_line = line;
+            },
+            Err(err) => {
+                let offset = err.bytecode_offset().unwrap_or(0);
+                let line = line_at(chunk, offset);
+                write_prefix(writer, offset, prev_line, line)?;
+                write_error(writer, chunk, &err)?;
+                prev_line = line;
+            }
         }
-        disassemble_instruction_impl(chunk, &mut reader, prev_line, line.unwrap());
     }
+    Ok(())
 }
 
-#[cfg(any(feature="debug-trace-execution"))]
-pub fn disassemble_instruction(chunk: &Chunk, offset: usize) {
-    let line = chunk.lines.into_iter().nth(offset).unwrap();
-    let prev_line = if offset > 0 { chunk.lines.into_iter().nth(offset - 1) } else { None };
+/// Disassemble register machine code, with each instruction
+/// prefixed by its index rather than a byte offset
+pub fn disassemble_register_chunk<W: Write>(writer: &mut W, chunk: &RegisterChunk, name: &str) -> io::Result<()> {
+    writeln!(writer, "== {} ==", name)?;
+
+    let mut prev_line = None;
+    for (index, (instruction, line)) in chunk.code().iter().zip(&chunk.lines).enumerate() {
+        write_prefix(writer, index, prev_line, Some(*line))?;
+        writeln!(writer, "{}", instruction)?;
+        prev_line = Some(*line);
+    }
+    for (index, constant) in chunk.constants.iter().enumerate() {
+        writeln!(writer, "k{} = {}", index, constant)?;
+    }
+    Ok(())
+}
 
-    let mut reader = Cursor::new(&chunk.code);
-    reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+/// Disassemble the instruction at the given offset,
+/// returning the offset of the next instruction
+pub fn disassemble_instruction<W: Write>(writer: &mut W, chunk: &Chunk, offset: usize) -> io::Result<usize> {
+    let line = line_at(chunk, offset);
+    let prev_line = if offset > 0 { line_at(chunk, offset - 1) } else { None };
+    write_prefix(writer, offset, prev_line, line)?;
 
-    disassemble_instruction_impl(chunk, &mut reader, prev_line, line);
+    match Instruction::decode(&chunk.code, offset) {
+        Ok((instruction, length)) => {
+            write_instruction(writer, chunk, &instruction)?;
+            Ok(offset + length)
+        },
+        Err(err) => {
+            write_error(writer, chunk, &err)?;
+            Ok(offset + 1)
+        },
+    }
 }
 
-fn disassemble_instruction_impl(chunk: &Chunk, reader: &mut Cursor<&Vec<u8>>, prev_line: Option<&usize>, line: &usize) {
-    print!("{:04} ", reader.position());
+fn line_at(chunk: &Chunk, offset: usize) -> Option<usize> {
+    chunk.lines.get(offset).cloned()
+}
 
-    match prev_line {
-        Some(prev_line) if prev_line == line => {
-            print!("   | ");
-        }
-        _ => {
-            print!("{:4} ", line);
-        }
-    };
-
-    let mut opcode_byte = [0u8];
-    reader.read(&mut opcode_byte).unwrap();
-    let opcode = OpCode::from_byte(opcode_byte[0]);
-    match opcode {
-        Some(o @ OpCode::Add) => simple_instruction(o),
-        Some(o @ OpCode::Constant) => constant_instruction(o, chunk, reader),
-        Some(o @ OpCode::ConstantLong) => constant_long_instruction(o, chunk, reader),
-        Some(o @ OpCode::True) => simple_instruction(o),
-        Some(o @ OpCode::False) => simple_instruction(o),
-        Some(o @ OpCode::Nil) => simple_instruction(o),
-        Some(o @ OpCode::Divide) => simple_instruction(o),
-        Some(o @ OpCode::Multiply) => simple_instruction(o),
-        Some(o @ OpCode::Negate) => simple_instruction(o),
-        Some(o @ OpCode::Return) => simple_instruction(o),
-        Some(o @ OpCode::Subtract) => simple_instruction(o),
-        Some(o @ OpCode::Not) => simple_instruction(o),
-        Some(o @ OpCode::Equal) => simple_instruction(o),
-        Some(o @ OpCode::Greater) => simple_instruction(o),
-        Some(o @ OpCode::Less) => simple_instruction(o),
-        None => {
-            println!("Unknown opcode: {}", opcode_byte[0]);
-        }
+fn write_prefix<W: Write>(writer: &mut W, offset: usize, prev_line: Option<usize>, line: Option<usize>) -> io::Result<()> {
+    write!(writer, "{:04} ", offset)?;
+    match (prev_line, line) {
+        (Some(prev_line), Some(line)) if prev_line == line => {
+            write!(writer, "   | ")
+        },
+        (_, Some(line)) => {
+            write!(writer, "{:4} ", line)
+        },
+        (_, None) => {
+            write!(writer, "   ? ")
+        },
     }
 }
 
-fn simple_instruction(opcode: OpCode) {
-    println!("OpCode::{:?}", opcode);
+fn write_instruction<W: Write>(writer: &mut W, chunk: &Chunk, instruction: &Instruction) -> io::Result<()> {
+    let opcode = instruction.op_code();
+    match instruction.constant_index() {
+        Some(constant_index) => match chunk.constants.get(constant_index) {
+            Some(value) => writeln!(writer, "OpCode::{:?} {:4} '{}'", opcode, constant_index, value),
+            None => writeln!(writer, "OpCode::{:?} {:4} <invalid>", opcode, constant_index),
+        },
+        None => writeln!(writer, "OpCode::{:?}", opcode),
+    }
 }
 
-fn constant_instruction<R: Read>(opcode: OpCode, chunk: &Chunk, reader: &mut R) {
-    let ConstantInstruction { constant_index } = ConstantInstruction::parse(reader);
-    let value = chunk.constants[constant_index as usize].clone();
-    println!("OpCode::{:?} {:4} '{}'", opcode, constant_index, value);
+fn write_error<W: Write>(writer: &mut W, chunk: &Chunk, err: &InterpretError) -> io::Result<()> {
+    match err {
+        InterpretError::UnknownOpCode { byte, .. } => writeln!(writer, "Unknown opcode: {}", byte),
+        InterpretError::TruncatedInstruction { offset } => match chunk.code.get(*offset).and_then(|&byte| OpCode::from_byte(byte)) {
+            Some(opcode) => writeln!(writer, "OpCode::{:?} <truncated>", opcode),
+            None => writeln!(writer, "<end of chunk>"),
+        },
+        InterpretError::MalformedOperand { offset } => {
+            let opcode = OpCode::from_byte(chunk.code[*offset]).unwrap();
+            writeln!(writer, "OpCode::{:?} <malformed operand>", opcode)
+        },
+        err => writeln!(writer, "{}", err),
+    }
 }
 
-fn constant_long_instruction<R: Read>(opcode: OpCode, chunk: &Chunk, reader: &mut R) {
-    let ConstantLongInstruction { constant_index } = ConstantLongInstruction::parse(reader);
-    let value = chunk.constants[constant_index as usize].clone();
-    println!("OpCode::{:?} {:4} '{}'", opcode, constant_index, value);
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::value::Value;
+
+    fn disassemble(chunk: &Chunk) -> String {
+        let mut output = Vec::new();
+        disassemble_chunk(&mut output, chunk, "test").unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_disassemble_chunk() {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::number(1.0), 1).unwrap();
+        chunk.write_instruction(SimpleInstruction::new(OpCode::Negate), 1);
+        chunk.write_instruction(SimpleInstruction::new(OpCode::Return), 2);
+
+        assert_eq!(disassemble(&chunk), "\
+== test ==
+0000    1 OpCode::Constant    0 'Number(1)'
+0002    | OpCode::Negate
+0003    2 OpCode::Return
+");
+    }
+
+    #[test]
+    fn test_disassemble_register_chunk() {
+        let chunk = ::compiler::compile_unoptimised("1 +\n-true").unwrap();
+        let register_chunk = ::register_compiler::translate(&chunk).unwrap();
+
+        let mut output = Vec::new();
+        disassemble_register_chunk(&mut output, &register_chunk, "test").unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+== test ==
+0000    2 LoadBool r0 true
+0001    | Negate r0 r0
+0002    | Add r0 k0 r0
+0003    | Return r0
+k0 = Number(1)
+");
+    }
+
+    #[test]
+    fn test_disassemble_instruction() {
+        let mut chunk = Chunk::new();
+        chunk.write_instruction(SimpleInstruction::new(OpCode::Nil), 1);
+        chunk.write_constant(Value::number(2.0), 3).unwrap();
+
+        let mut output = Vec::new();
+        let next_offset = disassemble_instruction(&mut output, &chunk, 1).unwrap();
+
+        assert_eq!(next_offset, 3);
+        assert_eq!(String::from_utf8(output).unwrap(), "0001    3 OpCode::Constant    0 'Number(2)'\n");
+    }
+
+    #[test]
+    fn test_disassemble_all_op_codes() {
+        // Every op code should be recognised by the disassembler
+        // and consume all of its operands.
+        for byte in 0..=255u8 {
+            let opcode = match OpCode::from_byte(byte) {
+                Some(opcode) => opcode,
+                None => continue,
+            };
+            let mut chunk = Chunk::new();
+            chunk.constants.push(Value::nil());
+            chunk.code = vec![byte, 0, 0, 0, 0];
+            for _ in 0..chunk.code.len() {
+                chunk.lines.push(1);
+            }
+
+            let mut output = Vec::new();
+            let next_offset = disassemble_instruction(&mut output, &chunk, 0).unwrap();
+            let output = String::from_utf8(output).unwrap();
+
+            assert!(
+                output.contains(&format!("OpCode::{:?}", opcode)),
+                "Expected {:?} to be disassembled but got '{}'", opcode, output);
+            assert!(
+                !output.contains("<"),
+                "Expected {:?} operands to be disassembled but got '{}'", opcode, output);
+            assert!(next_offset > 0, "Expected {:?} to be consumed", opcode);
+        }
+    }
+
+    #[test]
+    fn test_disassemble_unknown_op_code() {
+        let mut chunk = Chunk::new();
+        chunk.write_instruction(SimpleInstruction::new(OpCode::Return), 1);
+        chunk.code[0] = 255;
+
+        assert_eq!(disassemble(&chunk), "== test ==\n0000    1 Unknown opcode: 255\n");
+    }
 }
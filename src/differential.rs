@@ -0,0 +1,205 @@
+use std::fmt;
+
+use ::compiler::{self, OptimisationLevel};
+use ::errors::{InterpretError, InterpretResult};
+use ::parser;
+use ::register_compiler;
+use ::register_machine::RegisterMachine;
+use ::tree_walker;
+use ::value::Value;
+use ::virtual_machine::VirtualMachine;
+
+/// How running a program ended, in a form that can be compared
+/// between implementations. Compile error messages and error lines
+/// aren't compared, as the compiler and parser report them differently.
+#[derive(Debug,Clone,PartialEq)]
+pub enum Outcome {
+    /// The result of the program, as it would be printed
+    Value(String),
+    CompileError,
+    /// A runtime error, with its message
+    RuntimeError(String),
+    /// Any other error, such as from malformed bytecode, which
+    /// the reference interpreter can never produce
+    Other(String),
+}
+
+impl Outcome {
+    fn from_result(result: InterpretResult<Value>) -> Outcome {
+        match result {
+            Ok(value) => Outcome::Value(value.to_string()),
+            Err(InterpretError::CompileError(_)) => Outcome::CompileError,
+            Err(InterpretError::RuntimeError(details)) => Outcome::RuntimeError(details.message),
+            Err(err) => Outcome::Other(err.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Value(value) => write!(f, "{}", value),
+            Outcome::CompileError => write!(f, "Compile error"),
+            Outcome::RuntimeError(message) => write!(f, "Runtime error: {}", message),
+            Outcome::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// A bytecode implementation that gave a different
+/// outcome to the reference interpreter
+#[derive(Debug,Clone,PartialEq)]
+pub struct Difference {
+    pub implementation: String,
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: expected '{}', got '{}'", self.implementation, self.expected, self.actual)
+    }
+}
+
+/// Run a program with the tree walking reference interpreter
+pub fn reference_outcome(source: &str) -> Outcome {
+    Outcome::from_result(parser::parse(source).and_then(|expr| tree_walker::evaluate(&expr)))
+}
+
+/// Run a program with the stack virtual machine
+pub fn stack_outcome(source: &str, optimisation: OptimisationLevel) -> Outcome {
+    Outcome::from_result(compiler::compile_with_optimisation(source, optimisation)
+        .and_then(|chunk| VirtualMachine::new().interpret(&chunk)))
+}
+
+/// Run a program with the register machine
+pub fn register_outcome(source: &str, optimisation: OptimisationLevel) -> Outcome {
+    Outcome::from_result(compiler::compile_with_optimisation(source, optimisation)
+        .and_then(|chunk| register_compiler::translate(&chunk))
+        .and_then(|chunk| RegisterMachine::new().interpret(&chunk)))
+}
+
+/// Run a program through the reference interpreter and every
+/// combination of bytecode backend and optimisation level,
+/// returning any that gave a different outcome to the reference
+pub fn check(source: &str) -> Vec<Difference> {
+    let expected = reference_outcome(source);
+    let mut differences = Vec::new();
+    for &optimisation in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+        let outcomes = vec![
+            ("stack", stack_outcome(source, optimisation)),
+            ("register", register_outcome(source, optimisation)),
+        ];
+        for (backend, actual) in outcomes {
+            if actual != expected {
+                differences.push(Difference {
+                    implementation: format!("{} backend with {:?} optimisation", backend, optimisation),
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+    differences
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use super::*;
+
+    fn assert_no_differences(source: &str) {
+        let differences: Vec<String> = check(source).iter().map(|d| d.to_string()).collect();
+        assert!(differences.is_empty(), "Running '{}':\n{}", source, differences.join("\n"));
+    }
+
+    /// Generates random expressions from a fixed seed, using
+    /// literals chosen to hit edge cases in folding and comparisons
+    struct ExpressionGenerator {
+        state: u64,
+    }
+
+    impl ExpressionGenerator {
+        fn next(&mut self, bound: usize) -> usize {
+            // xorshift64
+            self.state ^= self.state << 13;
+            self.state ^= self.state >> 7;
+            self.state ^= self.state << 17;
+            (self.state % bound as u64) as usize
+        }
+
+        fn expression(&mut self, depth: usize) -> String {
+            const LITERALS: [&str; 11] = ["0", "1", "2.5", "0.1", "1e308", "nil", "true", "false", "\"\"", "\"a\"", "\"b\""];
+            const UNARY: [&str; 2] = ["-", "!"];
+            const BINARY: [&str; 10] = ["+", "-", "*", "/", "==", "!=", "<", "<=", ">", ">="];
+            match if depth == 0 { 0 } else { self.next(4) } {
+                0 => LITERALS[self.next(LITERALS.len())].to_string(),
+                1 => format!("{}{}", UNARY[self.next(UNARY.len())], self.expression(depth - 1)),
+                _ => format!("({} {} {})",
+                    self.expression(depth - 1), BINARY[self.next(BINARY.len())], self.expression(depth - 1)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_outcomes() {
+        assert_eq!(reference_outcome("1 + 2"), Outcome::Value("Number(3)".to_string()));
+        assert_eq!(reference_outcome("1 +"), Outcome::CompileError);
+        assert_eq!(stack_outcome("1 +", OptimisationLevel::None), Outcome::CompileError);
+        assert_eq!(register_outcome("-nil", OptimisationLevel::Basic),
+            Outcome::RuntimeError("Operand must be a number".to_string()));
+    }
+
+    #[test]
+    fn test_sample_scripts() {
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+        let mut count = 0;
+        for entry in fs::read_dir(samples).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "lox") {
+                assert_no_differences(&fs::read_to_string(&path).unwrap());
+                count += 1;
+            }
+        }
+        assert!(count > 0, "Expected sample scripts to run");
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        let sources = vec![
+            "-0 == 0",
+            "!!nil",
+            "!!(1 < 2)",
+            "!(1 == 1)",
+            "-(-(1))",
+            "\"a\" + \"\" == \"a\"",
+            "1e308 * 10 - 1e308 * 10",
+            "(1 + 2) * (3 + 4) + (1 + 2)",
+            "1 + nil",
+            "2 * -true",
+            ")",
+            "1 1",
+        ];
+        for source in sources {
+            assert_no_differences(source);
+        }
+    }
+
+    #[test]
+    fn test_deeply_nested_expressions() {
+        let nested = |left: &str, depth: usize| format!("{}1{}", left.repeat(depth), ")".repeat(depth));
+        assert_no_differences(&nested("1 + (", 300));
+        assert_no_differences(&nested("nil == (", 300));
+        assert_no_differences(&nested("-(", 300));
+    }
+
+    #[test]
+    fn test_generated_expressions() {
+        let mut generator = ExpressionGenerator { state: 0x2545_f491_4f6c_dd1d };
+        for _ in 0..2_000 {
+            let source = generator.expression(4);
+            assert_no_differences(&source);
+        }
+    }
+}
@@ -6,8 +6,72 @@ use std::io;
 #[derive(Debug)]
 pub enum InterpretError {
     CompileError(String),
-    RuntimeError(String),
+    RuntimeError(RuntimeErrorDetails),
     IoError(io::Error),
+    /// A compiled bytecode file couldn't be loaded
+    InvalidBytecode(String),
+    // Errors due to malformed bytecode, with the offset
+    // of the instruction that couldn't be executed
+    StackUnderflow { offset: usize },
+    TruncatedInstruction { offset: usize },
+    MalformedOperand { offset: usize },
+    BadConstantIndex { offset: usize, index: usize },
+    UnknownOpCode { offset: usize, byte: u8 },
+    /// A chunk needs a deeper stack than the virtual machine allows
+    StackLimitExceeded { depth: usize, limit: usize },
+}
+
+/// Details of an error raised while executing a chunk, including the
+/// trace of frames that were active when it occurred.
+#[derive(Debug)]
+pub struct RuntimeErrorDetails {
+    pub message: String,
+    /// Active frames, ordered innermost first
+    pub stack_trace: Vec<StackTraceFrame>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct StackTraceFrame {
+    pub function_name: String,
+    pub source_file: Option<String>,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+// Exit codes from sysexits.h, as used by the reference Lox implementation
+pub const EXIT_USAGE: i32 = 64;
+pub const EXIT_DATA_ERROR: i32 = 65;
+pub const EXIT_SOFTWARE: i32 = 70;
+pub const EXIT_IO_ERROR: i32 = 74;
+
+impl InterpretError {
+    /// Process exit code to use when this error isn't handled
+    pub fn exit_code(&self) -> i32 {
+        match &self {
+            InterpretError::CompileError(_)
+                | InterpretError::InvalidBytecode(_) => EXIT_DATA_ERROR,
+            InterpretError::IoError(_) => EXIT_IO_ERROR,
+            InterpretError::RuntimeError(_)
+                | InterpretError::StackUnderflow { .. }
+                | InterpretError::TruncatedInstruction { .. }
+                | InterpretError::MalformedOperand { .. }
+                | InterpretError::BadConstantIndex { .. }
+                | InterpretError::UnknownOpCode { .. }
+                | InterpretError::StackLimitExceeded { .. } => EXIT_SOFTWARE,
+        }
+    }
+
+    /// The offset of the instruction that caused an error due to malformed bytecode
+    pub fn bytecode_offset(&self) -> Option<usize> {
+        match &self {
+            InterpretError::StackUnderflow { offset }
+                | InterpretError::TruncatedInstruction { offset }
+                | InterpretError::MalformedOperand { offset }
+                | InterpretError::BadConstantIndex { offset, .. }
+                | InterpretError::UnknownOpCode { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for InterpretError {
@@ -16,16 +80,60 @@ impl fmt::Display for InterpretError {
             InterpretError::CompileError(details) => write!(f,"Compile error: {}", details),
             InterpretError::RuntimeError(details) => write!(f,"Runtime error: {}", details),
             InterpretError::IoError(io_error) => write!(f,"IO error: {}", io_error),
+            InterpretError::InvalidBytecode(details) => write!(f,"Invalid bytecode: {}", details),
+            InterpretError::StackUnderflow { offset } =>
+                write!(f,"Runtime error: Stack underflow at offset {}", offset),
+            InterpretError::TruncatedInstruction { offset } =>
+                write!(f,"Runtime error: Truncated instruction at offset {}", offset),
+            InterpretError::MalformedOperand { offset } =>
+                write!(f,"Runtime error: Malformed operand at offset {}", offset),
+            InterpretError::BadConstantIndex { offset, index } =>
+                write!(f,"Runtime error: Bad constant index {} at offset {}", index, offset),
+            InterpretError::UnknownOpCode { offset, byte } =>
+                write!(f,"Runtime error: Unknown op code {} at offset {}", byte, offset),
+            InterpretError::StackLimitExceeded { depth, limit } =>
+                write!(f,"Runtime error: Stack depth of {} exceeds the limit of {}", depth, limit),
         }
     }
 }
 
+impl fmt::Display for RuntimeErrorDetails {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        for frame in &self.stack_trace {
+            write!(f, "\n    {}", frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for StackTraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at {} (", self.function_name)?;
+        if let Some(source_file) = &self.source_file {
+            write!(f, "{}, ", source_file)?;
+        }
+        write!(f, "line {}", self.line)?;
+        if let Some(column) = self.column {
+            write!(f, ", column {}", column)?;
+        }
+        write!(f, ")")
+    }
+}
+
 impl Error for InterpretError {
     fn description(&self) -> &str {
         match &self {
             InterpretError::CompileError(details) => &details,
-            InterpretError::RuntimeError(details) => &details,
+            InterpretError::RuntimeError(details) => &details.message,
             InterpretError::IoError(io_error) => &io_error.description(),
+            InterpretError::InvalidBytecode(details) => &details,
+            InterpretError::StackUnderflow { .. } => "Stack underflow",
+            InterpretError::TruncatedInstruction { .. } => "Truncated instruction",
+            InterpretError::MalformedOperand { .. } => "Malformed operand",
+            InterpretError::BadConstantIndex { .. } => "Bad constant index",
+            InterpretError::UnknownOpCode { .. } => "Unknown op code",
+            InterpretError::StackLimitExceeded { .. } => "Stack limit exceeded",
         }
     }
 
@@ -44,3 +152,49 @@ impl convert::From<io::Error> for InterpretError {
 }
 
 pub type InterpretResult<T> = Result<T, InterpretError>;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let compile_error = InterpretError::CompileError("Compilation error occurred".to_string());
+        let runtime_error = InterpretError::RuntimeError(RuntimeErrorDetails {
+            message: "Operands must be numbers".to_string(),
+            stack_trace: vec![],
+        });
+        let io_error = InterpretError::IoError(io::Error::new(io::ErrorKind::NotFound, "missing"));
+        let bytecode_error = InterpretError::StackUnderflow { offset: 0 };
+
+        assert_eq!(compile_error.exit_code(), 65);
+        assert_eq!(runtime_error.exit_code(), 70);
+        assert_eq!(io_error.exit_code(), 74);
+        assert_eq!(bytecode_error.exit_code(), 70);
+    }
+
+    #[test]
+    fn test_runtime_error_display_includes_trace() {
+        let error = InterpretError::RuntimeError(RuntimeErrorDetails {
+            message: "Operand must be a number".to_string(),
+            stack_trace: vec![
+                StackTraceFrame {
+                    function_name: "inner".to_string(),
+                    source_file: Some("test.lox".to_string()),
+                    line: 3,
+                    column: Some(7),
+                },
+                StackTraceFrame {
+                    function_name: "script".to_string(),
+                    source_file: None,
+                    line: 10,
+                    column: None,
+                },
+            ],
+        });
+
+        assert_eq!(
+            format!("{}", error),
+            "Runtime error: Operand must be a number\n    at inner (test.lox, line 3, column 7)\n    at script (line 10)");
+    }
+}
@@ -0,0 +1,244 @@
+use ::chunk::Chunk;
+use ::errors::{InterpretError, InterpretResult};
+use ::instructions::Instruction;
+use ::value::Value;
+use ::virtual_machine::{is_falsey, values_equal};
+
+/// An instruction in a chunk being rebuilt, where instructions
+/// that push a literal value are kept as the value so they can
+/// be folded into the operations that use them
+enum Folded {
+    Literal(Value),
+    Instruction(Instruction),
+}
+
+/// Rebuild a chunk with operations on literal operands evaluated
+/// at compile time. Operations that would raise a runtime error
+/// are left alone, so the error is still reported when running.
+pub fn fold_constants(chunk: &Chunk) -> InterpretResult<Chunk> {
+    let mut folded: Vec<(Folded, usize)> = Vec::new();
+
+    for result in chunk.instructions() {
+        let (offset, instruction, line) = result?;
+        let line = line.unwrap_or(0);
+        // Superinstructions are split up so that their constant can be folded
+        match instruction.split() {
+            Some((load, operation)) => {
+                fold_instruction(&mut folded, chunk, offset, load, line)?;
+                fold_instruction(&mut folded, chunk, offset, operation, line)?;
+            },
+            None => fold_instruction(&mut folded, chunk, offset, instruction, line)?,
+        }
+    }
+
+    let mut result = Chunk::new();
+    for (folded, line) in folded {
+        match folded {
+            Folded::Literal(ref value) if value.is_nil() => result.write_instruction(Instruction::Nil, line),
+            Folded::Literal(ref value) if value.is_bool() => {
+                let instruction = if value.as_bool() { Instruction::True } else { Instruction::False };
+                result.write_instruction(instruction, line);
+            },
+            Folded::Literal(value) => result.write_constant(value, line)?,
+            Folded::Instruction(instruction) => result.write_instruction(instruction, line),
+        }
+    }
+    Ok(result)
+}
+
+fn fold_instruction(folded: &mut Vec<(Folded, usize)>, chunk: &Chunk, offset: usize,
+                    instruction: Instruction, line: usize) -> InterpretResult<()> {
+    let literal = match instruction {
+        Instruction::Constant(_) => {
+            let index = instruction.constant_index().unwrap();
+            let value = chunk.constants.get(index)
+                .cloned()
+                .ok_or(InterpretError::BadConstantIndex { offset, index })?;
+            Some(value)
+        },
+        Instruction::Nil => Some(Value::nil()),
+        Instruction::True => Some(Value::bool(true)),
+        Instruction::False => Some(Value::bool(false)),
+        _ => None,
+    };
+    if let Some(value) = literal {
+        folded.push((Folded::Literal(value), line));
+        return Ok(());
+    }
+
+    let (pops, _) = instruction.op_code().stack_effect();
+    let operands = literal_operands(folded, pops);
+    let value = match operands.as_slice() {
+        [operand] => fold_unary(instruction, operand),
+        [left, right] => fold_binary(instruction, left, right),
+        _ => None,
+    };
+    match value {
+        Some(value) => {
+            let length = folded.len();
+            folded.truncate(length - pops);
+            folded.push((Folded::Literal(value), line));
+        },
+        None => folded.push((Folded::Instruction(instruction), line)),
+    }
+    Ok(())
+}
+
+/// Get the values of the operands an instruction would pop,
+/// if they are all pushed by the preceding literal instructions.
+/// There are no jumps, so these are always the values on the
+/// top of the stack when the instruction executes.
+fn literal_operands(folded: &[(Folded, usize)], count: usize) -> Vec<Value> {
+    if count == 0 || count > folded.len() {
+        return Vec::new();
+    }
+    folded[folded.len() - count..].iter()
+        .map(|(folded, _)| match folded {
+            Folded::Literal(value) => Some(value.clone()),
+            Folded::Instruction(_) => None,
+        })
+        .collect::<Option<Vec<Value>>>()
+        .unwrap_or_default()
+}
+
+fn fold_unary(instruction: Instruction, operand: &Value) -> Option<Value> {
+    match (instruction, operand) {
+        (Instruction::Not, operand) => Some(Value::bool(is_falsey(operand.clone()))),
+        (Instruction::Negate, operand) if operand.is_number() => Some(Value::number(-operand.as_number())),
+        _ => None,
+    }
+}
+
+fn fold_binary(instruction: Instruction, left: &Value, right: &Value) -> Option<Value> {
+    match instruction {
+        Instruction::Equal => return Some(Value::bool(values_equal(left.clone(), right.clone()))),
+        Instruction::NotEqual => return Some(Value::bool(!values_equal(left.clone(), right.clone()))),
+        _ => {},
+    }
+    if let (Instruction::Add, true, true) = (instruction, left.is_string(), right.is_string()) {
+        let value = format!("{}{}", left.as_string(), right.as_string());
+        return Some(Value::string(value));
+    }
+    if !(left.is_number() && right.is_number()) {
+        return None;
+    }
+    let (a, b) = (left.as_number(), right.as_number());
+    match instruction {
+        Instruction::Add => Some(Value::number(a + b)),
+        Instruction::Subtract => Some(Value::number(a - b)),
+        Instruction::Multiply => Some(Value::number(a * b)),
+        Instruction::Divide => Some(Value::number(a / b)),
+        Instruction::Greater => Some(Value::bool(a > b)),
+        Instruction::Less => Some(Value::bool(a < b)),
+        Instruction::GreaterEqual => Some(Value::bool(a >= b)),
+        Instruction::LessEqual => Some(Value::bool(a <= b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+    use ::virtual_machine::VirtualMachine;
+
+    fn fold(source: &str) -> InterpretResult<Chunk> {
+        fold_constants(&compiler::compile_unoptimised(source)?)
+    }
+
+    fn run(source: &str) -> InterpretResult<Value> {
+        let chunk = fold(source)?;
+        VirtualMachine::new().interpret(&chunk)
+    }
+
+    #[test]
+    fn test_fold_arithmetic() {
+        let chunk = fold("2 * 3 + 4").unwrap();
+
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::Constant(0), Instruction::Return]);
+        assert_eq!(chunk.constants.len(), 1);
+        assert_eq!(chunk.constants[0].as_number(), 10.0);
+    }
+
+    #[test]
+    fn test_fold_comparisons_and_not() {
+        let chunk = fold("!(1 < 2) == (\"a\" + \"b\" == \"ab\")").unwrap();
+
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::False, Instruction::Return]);
+        assert_eq!(chunk.constants.len(), 0);
+    }
+
+    #[test]
+    fn test_fold_string_concatenation() {
+        let chunk = fold("\"a\" + \"b\" + \"c\"").unwrap();
+
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::Constant(0), Instruction::Return]);
+        assert_eq!(chunk.constants[0].as_string(), "abc");
+    }
+
+    #[test]
+    fn test_fold_preserves_ieee_semantics() {
+        let value = run("-0").unwrap();
+        assert!(value.as_number() == 0.0 && value.as_number().is_sign_negative());
+        assert!(run("0 / 0").unwrap().as_number().is_nan());
+        assert_eq!(run("1 / 0").unwrap().as_number(), f64::INFINITY);
+        assert!(!run("0 / 0 == 0 / 0").unwrap().as_bool());
+    }
+
+    #[test]
+    fn test_fold_comparisons_with_nan() {
+        let chunk = fold("(0 / 0 >= 1) == (0 / 0 <= 1)").unwrap();
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::True, Instruction::Return]);
+        let chunk = fold("0 / 0 >= 1").unwrap();
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::False, Instruction::Return]);
+        let chunk = fold("0 / 0 != 0 / 0").unwrap();
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::True, Instruction::Return]);
+    }
+
+    #[test]
+    fn test_fold_superinstructions() {
+        let mut chunk = Chunk::new();
+        chunk.constants.push(Value::number(1.0));
+        chunk.constants.push(Value::number(2.0));
+        chunk.write_instruction(Instruction::Constant(0), 1);
+        chunk.write_instruction(Instruction::AddConstant(1), 1);
+        chunk.write_instruction(Instruction::Return, 1);
+
+        let chunk = fold_constants(&chunk).unwrap();
+        assert_eq!(chunk.decoded_instructions(), vec![Instruction::Constant(0), Instruction::Return]);
+        assert_eq!(chunk.constants[0].as_number(), 3.0);
+    }
+
+    #[test]
+    fn test_partial_folding() {
+        let chunk = fold("-true + 1 * 2").unwrap();
+
+        assert_eq!(chunk.decoded_instructions(), vec![
+            Instruction::True,
+            Instruction::Negate,
+            Instruction::Constant(0),
+            Instruction::Add,
+            Instruction::Return,
+        ]);
+        assert_eq!(chunk.constants[0].as_number(), 2.0);
+    }
+
+    #[test]
+    fn test_runtime_errors_are_not_folded() {
+        let chunk = fold("1 +\n\"a\"").unwrap();
+        assert_eq!(chunk.decoded_instructions(), vec![
+            Instruction::Constant(0),
+            Instruction::Constant(1),
+            Instruction::Add,
+            Instruction::Return,
+        ]);
+
+        match VirtualMachine::new().interpret(&chunk) {
+            Err(InterpretError::RuntimeError(details)) => {
+                assert_eq!(details.message, "Operands must be two numbers or two strings");
+                assert_eq!(details.stack_trace[0].line, 2);
+            },
+            result => assert!(false, "Expected runtime error, got {:?}", result),
+        }
+    }
+}
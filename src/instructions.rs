@@ -1,24 +1,33 @@
+use std::io;
 use std::io::{Read,Write};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::ReadBytesExt;
 use num_traits::FromPrimitive;
 
-#[derive(Debug,Copy,Clone,Primitive)]
+use ::errors::{InterpretError, InterpretResult};
+
+#[derive(Debug,Copy,Clone,PartialEq,Eq,Primitive)]
 pub enum OpCode {
     Return = 0,
     Constant = 1,
-    ConstantLong = 2,
-    Nil = 3,
-    True = 4,
-    False = 5,
-    Negate = 6,
-    Add = 7,
-    Subtract = 8,
-    Multiply = 9,
-    Divide = 10,
-    Not = 11,
-    Equal = 12,
-    Greater = 13,
-    Less = 14,
+    Nil = 2,
+    True = 3,
+    False = 4,
+    Negate = 5,
+    Add = 6,
+    Subtract = 7,
+    Multiply = 8,
+    Divide = 9,
+    Not = 10,
+    Equal = 11,
+    Greater = 12,
+    Less = 13,
+    NotEqual = 14,
+    GreaterEqual = 15,
+    LessEqual = 16,
+    AddConstant = 17,
+    SubtractConstant = 18,
+    LessConstant = 19,
+    GreaterConstant = 20,
 }
 
 impl OpCode {
@@ -29,65 +38,260 @@ impl OpCode {
     pub fn from_byte(byte: u8) -> Option<OpCode> {
         OpCode::from_u8(byte)
     }
-}
 
-pub trait InstructionRead {
-    fn parse<R: Read>(reader: &mut R) -> Self;
+    /// The number of values popped from and then pushed onto the stack
+    /// when executing an instruction with this op code
+    pub fn stack_effect(self) -> (usize, usize) {
+        match self {
+            OpCode::Return => (1, 0),
+            OpCode::Constant
+                | OpCode::Nil | OpCode::True | OpCode::False => (0, 1),
+            OpCode::Negate | OpCode::Not
+                | OpCode::AddConstant | OpCode::SubtractConstant
+                | OpCode::LessConstant | OpCode::GreaterConstant => (1, 1),
+            OpCode::Add | OpCode::Subtract | OpCode::Multiply | OpCode::Divide
+                | OpCode::Equal | OpCode::Greater | OpCode::Less
+                | OpCode::NotEqual | OpCode::GreaterEqual | OpCode::LessEqual => (2, 1),
+        }
+    }
+
+    /// A table of the op code for each byte value, for decoding
+    /// op codes without going through FromPrimitive
+    pub fn table() -> [Option<OpCode>; 256] {
+        let mut table = [None; 256];
+        for (byte, op_code) in table.iter_mut().enumerate() {
+            *op_code = OpCode::from_byte(byte as u8);
+        }
+        table
+    }
+
+    /// Find the op code with the given name, as formatted by Debug
+    pub fn from_name(name: &str) -> Option<OpCode> {
+        (0..=255u8)
+            .filter_map(OpCode::from_byte)
+            .find(|op_code| format!("{:?}", op_code) == name)
+    }
 }
 
-pub trait InstructionWrite {
-    fn write<W: Write>(&self, writer: &mut W);
+/// A decoded instruction, including its operands
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Instruction {
+    Return,
+    Constant(u32),
+    Nil,
+    True,
+    False,
+    Negate,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Equal,
+    Greater,
+    Less,
+    NotEqual,
+    GreaterEqual,
+    LessEqual,
+    /// Superinstructions that load a constant and use it as the right
+    /// hand operand of a binary operation, to save a dispatch
+    AddConstant(u32),
+    SubtractConstant(u32),
+    LessConstant(u32),
+    GreaterConstant(u32),
 }
 
-pub struct ConstantInstruction {
-    pub constant_index: u8,
+impl Instruction {
+    /// Decode the instruction at the given offset,
+    /// returning it along with its length in bytes
+    pub fn decode(code: &[u8], offset: usize) -> InterpretResult<(Instruction, usize)> {
+        let byte = *code.get(offset).ok_or(InterpretError::TruncatedInstruction { offset })?;
+        let op_code = OpCode::from_byte(byte).ok_or(InterpretError::UnknownOpCode { offset, byte })?;
+        let mut operands = &code[offset + 1..];
+        let instruction = match op_code {
+            OpCode::Return => Instruction::Return,
+            OpCode::Constant | OpCode::AddConstant | OpCode::SubtractConstant
+                | OpCode::LessConstant | OpCode::GreaterConstant => {
+                let constant_index = ConstantInstruction::parse(&mut operands)
+                    .map_err(|err| operand_error(err, offset))?
+                    .constant_index;
+                Instruction::with_constant(op_code, constant_index).unwrap()
+            },
+            OpCode::Nil => Instruction::Nil,
+            OpCode::True => Instruction::True,
+            OpCode::False => Instruction::False,
+            OpCode::Negate => Instruction::Negate,
+            OpCode::Add => Instruction::Add,
+            OpCode::Subtract => Instruction::Subtract,
+            OpCode::Multiply => Instruction::Multiply,
+            OpCode::Divide => Instruction::Divide,
+            OpCode::Not => Instruction::Not,
+            OpCode::Equal => Instruction::Equal,
+            OpCode::Greater => Instruction::Greater,
+            OpCode::Less => Instruction::Less,
+            OpCode::NotEqual => Instruction::NotEqual,
+            OpCode::GreaterEqual => Instruction::GreaterEqual,
+            OpCode::LessEqual => Instruction::LessEqual,
+        };
+        let length = code.len() - offset - operands.len();
+        Ok((instruction, length))
+    }
+
+    pub fn op_code(&self) -> OpCode {
+        match self {
+            Instruction::Return => OpCode::Return,
+            Instruction::Constant(_) => OpCode::Constant,
+            Instruction::Nil => OpCode::Nil,
+            Instruction::True => OpCode::True,
+            Instruction::False => OpCode::False,
+            Instruction::Negate => OpCode::Negate,
+            Instruction::Add => OpCode::Add,
+            Instruction::Subtract => OpCode::Subtract,
+            Instruction::Multiply => OpCode::Multiply,
+            Instruction::Divide => OpCode::Divide,
+            Instruction::Not => OpCode::Not,
+            Instruction::Equal => OpCode::Equal,
+            Instruction::Greater => OpCode::Greater,
+            Instruction::Less => OpCode::Less,
+            Instruction::NotEqual => OpCode::NotEqual,
+            Instruction::GreaterEqual => OpCode::GreaterEqual,
+            Instruction::LessEqual => OpCode::LessEqual,
+            Instruction::AddConstant(_) => OpCode::AddConstant,
+            Instruction::SubtractConstant(_) => OpCode::SubtractConstant,
+            Instruction::LessConstant(_) => OpCode::LessConstant,
+            Instruction::GreaterConstant(_) => OpCode::GreaterConstant,
+        }
+    }
+
+    /// Build an instruction with a constant index operand
+    pub fn with_constant(op_code: OpCode, constant_index: u32) -> Option<Instruction> {
+        match op_code {
+            OpCode::Constant => Some(Instruction::Constant(constant_index)),
+            OpCode::AddConstant => Some(Instruction::AddConstant(constant_index)),
+            OpCode::SubtractConstant => Some(Instruction::SubtractConstant(constant_index)),
+            OpCode::LessConstant => Some(Instruction::LessConstant(constant_index)),
+            OpCode::GreaterConstant => Some(Instruction::GreaterConstant(constant_index)),
+            _ => None,
+        }
+    }
+
+    /// Split a superinstruction into the constant load and
+    /// the instruction it replaces
+    pub fn split(&self) -> Option<(Instruction, Instruction)> {
+        match self {
+            Instruction::AddConstant(index) => Some((Instruction::Constant(*index), Instruction::Add)),
+            Instruction::SubtractConstant(index) => Some((Instruction::Constant(*index), Instruction::Subtract)),
+            Instruction::LessConstant(index) => Some((Instruction::Constant(*index), Instruction::Less)),
+            Instruction::GreaterConstant(index) => Some((Instruction::Constant(*index), Instruction::Greater)),
+            _ => None,
+        }
+    }
+
+    /// The index of the constant loaded by this instruction, if any
+    pub fn constant_index(&self) -> Option<usize> {
+        match self {
+            Instruction::Constant(index)
+                | Instruction::AddConstant(index)
+                | Instruction::SubtractConstant(index)
+                | Instruction::LessConstant(index)
+                | Instruction::GreaterConstant(index) => Some(*index as usize),
+            _ => None,
+        }
+    }
 }
 
-impl ConstantInstruction {
-    pub fn new(constant_index: u8) -> ConstantInstruction {
-        ConstantInstruction { constant_index }
+impl InstructionWrite for Instruction {
+    fn write<W: Write>(&self, writer: &mut W) {
+        match self {
+            Instruction::Constant(index) => ConstantInstruction::new(*index).write(writer),
+            Instruction::AddConstant(index)
+                | Instruction::SubtractConstant(index)
+                | Instruction::LessConstant(index)
+                | Instruction::GreaterConstant(index) => {
+                writer.write_all(&[self.op_code().as_byte()]).unwrap();
+                write_varint(writer, *index).unwrap();
+            },
+            _ => SimpleInstruction::new(self.op_code()).write(writer),
+        }
     }
 }
 
-impl InstructionRead for ConstantInstruction {
-    fn parse<R: Read>(reader: &mut R) -> ConstantInstruction {
-        let mut index = [0u8];
-        reader.read(&mut index).unwrap();
-        ConstantInstruction {
-            constant_index: index[0],
+pub trait InstructionRead: Sized {
+    /// Parse the instruction operands, after the op code has been read.
+    /// Fails if the reader ends before all operands are read.
+    fn parse<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+pub trait InstructionWrite {
+    fn write<W: Write>(&self, writer: &mut W);
+}
+
+/// Read an unsigned LEB128 varint operand, which stores seven bits
+/// in each byte, least significant first, with the high bit set on
+/// every byte except the last. Values up to 127 take a single byte.
+/// Fails with `InvalidData` if the value doesn't fit in a u32, or if it
+/// isn't in the shortest form, so that every value has one encoding.
+pub fn read_varint<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut value = 0u32;
+    let mut shift = 0;
+    loop {
+        let byte = reader.read_u8()?;
+        if shift == 28 && byte > 0x0f {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Varint operand is too large"));
         }
+        // A zero final byte adds nothing, so a shorter encoding exists
+        if shift > 0 && byte == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Varint operand is overlong"));
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
     }
 }
 
-impl InstructionWrite for ConstantInstruction {
-    fn write<W: Write>(&self, writer: &mut W) {
-        writer.write(&[OpCode::Constant.as_byte(), self.constant_index as u8]).unwrap();
+/// Write an unsigned LEB128 varint operand, as read by `read_varint`
+pub fn write_varint<W: Write>(writer: &mut W, mut value: u32) -> io::Result<()> {
+    while value >= 0x80 {
+        writer.write_all(&[(value & 0x7f) as u8 | 0x80])?;
+        value >>= 7;
     }
+    writer.write_all(&[value as u8])
 }
 
-pub struct ConstantLongInstruction {
+/// Convert an error from reading the operands of the instruction
+/// at the given offset
+pub fn operand_error(err: io::Error, offset: usize) -> InterpretError {
+    match err.kind() {
+        io::ErrorKind::UnexpectedEof => InterpretError::TruncatedInstruction { offset },
+        _ => InterpretError::MalformedOperand { offset },
+    }
+}
+
+pub struct ConstantInstruction {
     pub constant_index: u32,
 }
 
-impl ConstantLongInstruction {
-    pub fn new(constant_index: u32) -> ConstantLongInstruction {
-        ConstantLongInstruction { constant_index }
+impl ConstantInstruction {
+    pub fn new(constant_index: u32) -> ConstantInstruction {
+        ConstantInstruction { constant_index }
     }
 }
 
-impl InstructionRead for ConstantLongInstruction {
-    fn parse<R: Read>(reader: &mut R) -> ConstantLongInstruction {
-        let constant_index = reader.read_u32::<LittleEndian>().unwrap();
-        ConstantLongInstruction {
+impl InstructionRead for ConstantInstruction {
+    fn parse<R: Read>(reader: &mut R) -> io::Result<ConstantInstruction> {
+        let constant_index = read_varint(reader)?;
+        Ok(ConstantInstruction {
             constant_index,
-        }
+        })
     }
 }
 
-impl InstructionWrite for ConstantLongInstruction {
+impl InstructionWrite for ConstantInstruction {
     fn write<W: Write>(&self, writer: &mut W) {
-        writer.write(&[OpCode::ConstantLong.as_byte()]).unwrap();
-        writer.write_u32::<LittleEndian>(self.constant_index).unwrap();
+        writer.write_all(&[OpCode::Constant.as_byte()]).unwrap();
+        write_varint(writer, self.constant_index).unwrap();
     }
 }
 
@@ -103,6 +307,143 @@ impl SimpleInstruction {
 
 impl InstructionWrite for SimpleInstruction {
     fn write<W: Write>(&self, writer: &mut W) {
-        writer.write(&[self.op_code.as_byte()]).unwrap();
+        writer.write_all(&[self.op_code.as_byte()]).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::*;
+
+    #[test]
+    fn test_op_code_from_name() {
+        assert_eq!(OpCode::from_name("Constant"), Some(OpCode::Constant));
+        assert_eq!(OpCode::from_name("Less"), Some(OpCode::Less));
+        assert_eq!(OpCode::from_name("less"), None);
+    }
+
+    #[test]
+    fn test_op_code_table() {
+        let table = OpCode::table();
+        for byte in 0..=255u8 {
+            assert_eq!(table[byte as usize], OpCode::from_byte(byte));
+        }
+    }
+
+    #[test]
+    fn test_decode_and_write_all_op_codes() {
+        for byte in 0..=255u8 {
+            let op_code = match OpCode::from_byte(byte) {
+                Some(op_code) => op_code,
+                None => continue,
+            };
+            let code = vec![byte, 1, 2, 3, 4];
+            let (instruction, length) = Instruction::decode(&code, 0).unwrap();
+            assert_eq!(instruction.op_code(), op_code);
+
+            let mut written = Vec::new();
+            instruction.write(&mut written);
+            assert_eq!(written.len(), length);
+            assert_eq!(&written[..], &code[..length]);
+        }
+    }
+
+    #[test]
+    fn test_decode_at_offset() {
+        let code = vec![OpCode::Nil.as_byte(), OpCode::Constant.as_byte(), 7, OpCode::Return.as_byte()];
+        assert_eq!(Instruction::decode(&code, 1).unwrap(), (Instruction::Constant(7), 2));
+        assert_eq!(Instruction::decode(&code, 3).unwrap(), (Instruction::Return, 1));
+    }
+
+    #[test]
+    fn test_split_superinstructions() {
+        assert_eq!(Instruction::AddConstant(3).split(), Some((Instruction::Constant(3), Instruction::Add)));
+        assert_eq!(Instruction::LessConstant(0).split(), Some((Instruction::Constant(0), Instruction::Less)));
+        assert_eq!(Instruction::Add.split(), None);
+        assert_eq!(Instruction::Constant(3).split(), None);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let code = vec![OpCode::Nil.as_byte(), 255, OpCode::Constant.as_byte(), 0x81, 0x80];
+        match Instruction::decode(&code, 1) {
+            Err(InterpretError::UnknownOpCode { offset: 1, byte: 255 }) => {},
+            result => assert!(false, "Expected unknown op code, got {:?}", result),
+        }
+        match Instruction::decode(&code, 2) {
+            Err(InterpretError::TruncatedInstruction { offset: 2 }) => {},
+            result => assert!(false, "Expected truncated instruction, got {:?}", result),
+        }
+        match Instruction::decode(&code, 5) {
+            Err(InterpretError::TruncatedInstruction { offset: 5 }) => {},
+            result => assert!(false, "Expected truncated instruction, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_parse_constant() {
+        let code = vec![3u8];
+        let instruction = ConstantInstruction::parse(&mut Cursor::new(&code)).unwrap();
+        assert_eq!(instruction.constant_index, 3);
+    }
+
+    #[test]
+    fn test_parse_truncated_constant() {
+        let code = vec![];
+        let result = ConstantInstruction::parse(&mut Cursor::new(&code));
+        assert!(result.is_err(), "Expected error parsing truncated instruction");
+    }
+
+    #[test]
+    fn test_decode_malformed_operand() {
+        let code = vec![OpCode::Constant.as_byte(), 0x80, 0x80, 0x80, 0x80, 0x10];
+        match Instruction::decode(&code, 0) {
+            Err(InterpretError::MalformedOperand { offset: 0 }) => {},
+            result => assert!(false, "Expected malformed operand, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_decode_overlong_operand() {
+        let overlong_operands = vec![vec![0x80, 0x00], vec![0x81, 0x80, 0x00], vec![0xff, 0xff, 0xff, 0xff, 0x00]];
+        for operand in overlong_operands {
+            let mut code = vec![OpCode::Constant.as_byte()];
+            code.extend(&operand);
+            match Instruction::decode(&code, 0) {
+                Err(InterpretError::MalformedOperand { offset: 0 }) => {},
+                result => assert!(false, "Expected malformed operand for {:?}, got {:?}", operand, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        let values = [0, 1, 127, 128, 300, 16383, 16384, u32::MAX];
+        let lengths = [1, 1, 1, 2, 2, 2, 3, 5];
+        for (&value, &length) in values.iter().zip(lengths.iter()) {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value).unwrap();
+            assert_eq!(bytes.len(), length, "Length of {}", value);
+            assert_eq!(read_varint(&mut &bytes[..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_varint_encoding() {
+        let mut bytes = Vec::new();
+        write_varint(&mut bytes, 300).unwrap();
+        assert_eq!(bytes, vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_parse_multi_byte_constant() {
+        let code = vec![0x80u8, 0x02];
+        let instruction = ConstantInstruction::parse(&mut Cursor::new(&code)).unwrap();
+        assert_eq!(instruction.constant_index, 256);
+
+        let truncated = vec![0x80u8];
+        let result = ConstantInstruction::parse(&mut Cursor::new(&truncated));
+        assert!(result.is_err(), "Expected error parsing truncated instruction");
     }
 }
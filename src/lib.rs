@@ -0,0 +1,37 @@
+extern crate byteorder;
+#[macro_use]
+extern crate enum_primitive_derive;
+extern crate fnv;
+extern crate num_traits;
+extern crate rustyline;
+
+pub mod ast;
+pub mod chunk;
+pub mod errors;
+pub mod instructions;
+#[cfg(feature="nan-boxing")]
+pub mod nan_boxing;
+pub mod object;
+pub mod run_length_encoding;
+pub mod value;
+pub mod virtual_machine;
+pub mod assembler;
+pub mod compiler;
+pub mod folding;
+pub mod loader;
+pub mod parser;
+pub mod peephole;
+pub mod register_compiler;
+pub mod register_instructions;
+pub mod register_machine;
+pub mod repl;
+pub mod scanner;
+pub mod serialisation;
+pub mod string_interner;
+pub mod superinstructions;
+pub mod debug;
+#[cfg(test)]
+mod differential;
+pub mod trace;
+pub mod tree_walker;
+pub mod verifier;
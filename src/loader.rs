@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use ::assembler;
+use ::chunk::Chunk;
+use ::compiler::{self, OptimisationLevel};
+use ::errors::{InterpretError, InterpretResult};
+use ::serialisation;
+use ::verifier;
+use ::virtual_machine::DEFAULT_STACK_LIMIT;
+
+/// How chunks are loaded from input files
+#[derive(Debug,Copy,Clone)]
+pub struct LoadOptions {
+    pub optimisation: OptimisationLevel,
+    pub stack_limit: usize,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            optimisation: OptimisationLevel::Basic,
+            stack_limit: DEFAULT_STACK_LIMIT,
+        }
+    }
+}
+
+/// Load a chunk from a source file, a compiled bytecode file
+/// or a bytecode assembly listing with a .lasm extension.
+/// Only chunks compiled from source are optimised, and chunks that
+/// need a deeper stack than the limit are rejected whatever their source.
+pub fn load_file(file_path: &str, options: LoadOptions) -> InterpretResult<Chunk> {
+    let mut f = File::open(file_path)?;
+    let mut contents = Vec::new();
+    f.read_to_end(&mut contents)?;
+    let chunk = if serialisation::is_bytecode(&contents) {
+        let chunk = serialisation::read_chunk(&mut &contents[..])?;
+        verify(chunk)?
+    } else {
+        let source = String::from_utf8(contents)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if Path::new(file_path).extension().is_some_and(|extension| extension == "lasm") {
+            let assembly = assembler::assemble(&source)?;
+            verify(assembly.chunk)?
+        } else {
+            compiler::compile_with_optimisation(&source, options.optimisation)?
+        }
+    };
+    if chunk.max_stack_depth > options.stack_limit {
+        return Err(InterpretError::StackLimitExceeded {
+            depth: chunk.max_stack_depth,
+            limit: options.stack_limit,
+        });
+    }
+    Ok(chunk)
+}
+
+/// Check a chunk that didn't come from the compiler before it is run
+fn verify(chunk: Chunk) -> InterpretResult<Chunk> {
+    let violations = verifier::verify(&chunk);
+    if !violations.is_empty() {
+        let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        return Err(InterpretError::InvalidBytecode(
+            format!("Verification failed:\n{}", violations.join("\n"))));
+    }
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use ::instructions::Instruction;
+
+    /// Write a file to the temporary directory, returning its path
+    fn write_temp_file(name: &str, contents: &[u8]) -> String {
+        let path = env::temp_dir().join(format!("rlox_loader_{}", name));
+        File::create(&path).unwrap().write_all(contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_load_source_with_optimisation() {
+        let path = write_temp_file("optimisation.lox", b"1 + 2");
+        let options = LoadOptions { optimisation: OptimisationLevel::None, ..LoadOptions::default() };
+        let unoptimised = load_file(&path, options).unwrap();
+        let optimised = load_file(&path, LoadOptions::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(unoptimised.decoded_instructions(), vec![
+            Instruction::Constant(0), Instruction::Constant(1), Instruction::Add, Instruction::Return]);
+        assert_eq!(optimised.decoded_instructions(), vec![Instruction::Constant(0), Instruction::Return]);
+    }
+
+    #[test]
+    fn test_load_bytecode_and_assembly() {
+        let chunk = compiler::compile("-(1 + 2)").unwrap();
+        let mut data = Vec::new();
+        serialisation::write_chunk(&mut data, &chunk).unwrap();
+        let path = write_temp_file("bytecode.loxc", &data);
+        let loaded = load_file(&path, LoadOptions::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.decoded_instructions(), chunk.decoded_instructions());
+
+        let path = write_temp_file("listing.lasm", b"1 OpCode::True\nOpCode::Not\nOpCode::Return");
+        let loaded = load_file(&path, LoadOptions::default()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.decoded_instructions(), vec![Instruction::True, Instruction::Not, Instruction::Return]);
+    }
+
+    #[test]
+    fn test_reject_invalid_assembly() {
+        let path = write_temp_file("invalid.lasm", b"1 OpCode::Add\nOpCode::Return");
+        let result = load_file(&path, LoadOptions::default());
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(InterpretError::InvalidBytecode(_)) => {},
+            _ => assert!(false, "Expected invalid bytecode error"),
+        }
+    }
+
+    #[test]
+    fn test_reject_chunks_over_stack_limit() {
+        let path = write_temp_file("deep.lox", b"1 + (2 + (3 + 4))");
+        let options = LoadOptions { optimisation: OptimisationLevel::None, stack_limit: 2 };
+        let result = load_file(&path, options);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(InterpretError::StackLimitExceeded { depth: 4, limit: 2 }) => {},
+            _ => assert!(false, "Expected stack limit error"),
+        }
+    }
+}
@@ -1,29 +1,17 @@
-extern crate byteorder;
 extern crate clap;
-#[macro_use]
-extern crate enum_primitive_derive;
-extern crate fnv;
-extern crate num_traits;
+extern crate rlox;
 
-mod chunk;
-mod errors;
-mod instructions;
-mod object;
-mod run_length_encoding;
-mod value;
-mod virtual_machine;
-mod compiler;
-mod scanner;
-mod string_interner;
-
-#[cfg(any(feature="debug-print-code", feature="debug-trace-execution"))]
-mod debug;
-
-use clap::{Arg, App};
-use errors::InterpretResult;
-use std::io::{self, BufRead, Read};
+use clap::{Arg, App, ArgMatches, SubCommand};
+use rlox::{debug, register_compiler, repl, serialisation, trace};
+use rlox::compiler::OptimisationLevel;
+use rlox::errors::{InterpretResult, EXIT_USAGE};
+use rlox::loader::{load_file, LoadOptions};
+use rlox::register_machine::RegisterMachine;
+use rlox::trace::{TraceFormat, TraceOptions, Tracer};
+use rlox::virtual_machine::{VirtualMachine, DEFAULT_STACK_LIMIT};
+use std::io;
 use std::fs::File;
-use virtual_machine::VirtualMachine;
+use std::path::Path;
 
 fn main() {
     let args = App::new("rlox")
@@ -31,38 +19,189 @@ fn main() {
         .arg(Arg::with_name("input")
              .help("Source file to run")
              .index(1))
-        .get_matches();
+        .arg(Arg::with_name("disassemble")
+             .long("disassemble")
+             .help("Show the compiled bytecode instead of running it")
+             .requires("input"))
+        .arg(Arg::with_name("trace")
+             .long("trace")
+             .help("Trace each instruction as it is executed"))
+        .arg(Arg::with_name("trace-format")
+             .long("trace-format")
+             .help("Format of the trace output")
+             .takes_value(true)
+             .possible_values(&["text", "json"])
+             .requires("trace"))
+        .arg(Arg::with_name("trace-lines")
+             .long("trace-lines")
+             .value_name("START-END")
+             .help("Only trace instructions from this range of lines")
+             .takes_value(true)
+             .validator(|range| trace::parse_line_range(&range)
+                 .map(|_| ())
+                 .ok_or_else(|| format!("Invalid line range '{}'", range)))
+             .requires("trace"))
+        .arg(Arg::with_name("trace-ops")
+             .long("trace-ops")
+             .value_name("OPCODES")
+             .help("Only trace these comma separated op codes")
+             .takes_value(true)
+             .validator(|op_codes| trace::parse_op_codes(&op_codes)
+                 .map(|_| ())
+                 .ok_or_else(|| format!("Invalid op codes '{}'", op_codes)))
+             .requires("trace"))
+        .arg(Arg::with_name("stack-limit")
+             .long("stack-limit")
+             .value_name("DEPTH")
+             .help("Refuse to load chunks that need a deeper stack than this")
+             .takes_value(true)
+             .validator(|depth| depth.parse::<usize>()
+                 .map(|_| ())
+                 .map_err(|_| format!("Invalid stack depth '{}'", depth)))
+             .global(true))
+        .arg(Arg::with_name("backend")
+             .long("backend")
+             .help("Virtual machine to run the compiled code with")
+             .takes_value(true)
+             .possible_values(&["stack", "register"])
+             .default_value("stack"))
+        .arg(Arg::with_name("optimisation")
+             .short("O")
+             .value_name("LEVEL")
+             .help("Optimisation level for compiling source files, 0 to disable optimisation")
+             .takes_value(true)
+             .possible_values(&["0", "1"])
+             .default_value("1")
+             .global(true))
+        .subcommand(SubCommand::with_name("compile")
+             .about("Compile a source file to a bytecode file")
+             .arg(Arg::with_name("input")
+                  .help("Source file to compile")
+                  .required(true)
+                  .index(1))
+             .arg(Arg::with_name("output")
+                  .short("o")
+                  .long("output")
+                  .value_name("FILE")
+                  .help("Bytecode file to write, defaults to the input path with a .loxc extension")
+                  .takes_value(true)))
+        .get_matches_safe()
+        .unwrap_or_else(|err| {
+            if err.use_stderr() {
+                eprintln!("{}", err.message);
+                std::process::exit(EXIT_USAGE);
+            }
+            // Help or version information was requested
+            err.exit()
+        });
 
-    let result = match args.value_of("input") {
-        Some(input_path) => run_file(input_path),
-        _ => run_repl()
+    let trace_options = trace_options(&args);
+    let backend = backend(&args);
+    if backend == Backend::Register && trace_options.is_some() {
+        eprintln!("Tracing is only supported with the stack backend");
+        std::process::exit(EXIT_USAGE);
+    }
+    let runs_repl = args.subcommand_matches("compile").is_none() && args.value_of("input").is_none();
+    if backend == Backend::Register && runs_repl {
+        eprintln!("The REPL is only supported with the stack backend");
+        std::process::exit(EXIT_USAGE);
+    }
+    let result = if let Some(compile_args) = args.subcommand_matches("compile") {
+        let input_path = compile_args.value_of("input").unwrap();
+        let output_path = compile_args.value_of("output")
+            .map(|path| path.to_string())
+            .unwrap_or_else(|| Path::new(input_path).with_extension("loxc").to_string_lossy().into_owned());
+        compile_to_file(input_path, &output_path, load_options(compile_args))
+    } else {
+        let options = load_options(&args);
+        match args.value_of("input") {
+            Some(input_path) if args.is_present("disassemble") => disassemble_file(input_path, options, backend),
+            Some(input_path) => run_file(input_path, options, backend, trace_options),
+            _ => repl::run_repl(trace_options, options)
+        }
     };
 
     if let Err(err) = result {
         eprintln!("{}", err);
-        std::process::exit(1);
+        std::process::exit(err.exit_code());
     };
 }
 
-fn run_file(file_path: &str) -> InterpretResult<()> {
-    let mut f = File::open(file_path)?;
-    let mut contents = String::new();
-    f.read_to_string(&mut contents)?;
-    interpret(&contents)?;
+/// The virtual machine used to run code
+#[derive(Debug,Copy,Clone,PartialEq)]
+enum Backend {
+    Stack,
+    Register,
+}
+
+fn backend(args: &ArgMatches) -> Backend {
+    match args.value_of("backend") {
+        Some("register") => Backend::Register,
+        _ => Backend::Stack,
+    }
+}
+
+fn trace_options(args: &ArgMatches) -> Option<TraceOptions> {
+    if !args.is_present("trace") {
+        return None;
+    }
+    let format = match args.value_of("trace-format") {
+        Some("json") => TraceFormat::JsonLines,
+        _ => TraceFormat::Text,
+    };
+    let mut options = TraceOptions::new(format);
+    // Values have already been validated when parsing arguments
+    options.lines = args.value_of("trace-lines").and_then(trace::parse_line_range);
+    options.op_codes = args.value_of("trace-ops").and_then(trace::parse_op_codes);
+    Some(options)
+}
+
+fn load_options(args: &ArgMatches) -> LoadOptions {
+    let optimisation = match args.value_of("optimisation") {
+        Some("0") => OptimisationLevel::None,
+        _ => OptimisationLevel::Basic,
+    };
+    // Value has already been validated when parsing arguments
+    let stack_limit = args.value_of("stack-limit")
+        .map_or(DEFAULT_STACK_LIMIT, |depth| depth.parse().unwrap());
+    LoadOptions { optimisation, stack_limit }
+}
+
+fn run_file(file_path: &str, options: LoadOptions, backend: Backend,
+            trace_options: Option<TraceOptions>) -> InterpretResult<()> {
+    let chunk = load_file(file_path, options)?;
+    match backend {
+        Backend::Stack => {
+            let mut vm = VirtualMachine::new();
+            vm.set_stack_limit(options.stack_limit);
+            vm.set_source_file(Some(file_path));
+            vm.set_tracer(trace_options.map(Tracer::stdout));
+            vm.interpret(&chunk)?;
+        },
+        Backend::Register => {
+            let register_chunk = register_compiler::translate(&chunk)?;
+            let mut machine = RegisterMachine::new();
+            machine.set_source_file(Some(file_path));
+            machine.interpret(&register_chunk)?;
+        },
+    }
     Ok(())
 }
 
-fn run_repl() -> InterpretResult<()> {
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        interpret(&line?)?;
+fn disassemble_file(file_path: &str, options: LoadOptions, backend: Backend) -> InterpretResult<()> {
+    let chunk = load_file(file_path, options)?;
+    match backend {
+        Backend::Stack => debug::disassemble_chunk(&mut io::stdout(), &chunk, file_path)?,
+        Backend::Register => {
+            let register_chunk = register_compiler::translate(&chunk)?;
+            debug::disassemble_register_chunk(&mut io::stdout(), &register_chunk, file_path)?;
+        },
     }
-    println!("");
     Ok(())
 }
 
-fn interpret(line: &str) -> InterpretResult<()> {
-    let chunk = compiler::compile(line)?;
-    let mut vm = VirtualMachine::new(&chunk);
-    vm.interpret()
+fn compile_to_file(input_path: &str, output_path: &str, options: LoadOptions) -> InterpretResult<()> {
+    let chunk = load_file(input_path, options)?;
+    let mut f = File::create(output_path)?;
+    serialisation::write_chunk(&mut f, &chunk)
 }
@@ -0,0 +1,192 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+use ::object::{LoxObject};
+
+/// Bits that are set in every quiet NaN, plus one more so that the
+/// NaN produced by arithmetic is still stored as a number
+const QUIET_NAN: u64 = 0x7ffc_0000_0000_0000;
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+const NIL_VALUE: u64 = QUIET_NAN | TAG_NIL;
+const FALSE_VALUE: u64 = QUIET_NAN | TAG_FALSE;
+const TRUE_VALUE: u64 = QUIET_NAN | TAG_TRUE;
+
+/// A value stored in 8 bytes. Numbers are stored as their f64 bits,
+/// and other values are stored in the unused bits of a quiet NaN:
+/// nil and bools as a small tag, and objects as a pointer with the
+/// sign bit set. Objects are reference counted, as with `Rc`.
+pub struct Value {
+    bits: u64,
+    /// Values may own an `Rc`, so mustn't be `Send` or `Sync`
+    marker: PhantomData<Rc<LoxObject>>,
+}
+
+impl Value {
+    fn from_bits(bits: u64) -> Value {
+        Value { bits, marker: PhantomData }
+    }
+
+    pub fn nil() -> Value {
+        Value::from_bits(NIL_VALUE)
+    }
+
+    pub fn bool(val: bool) -> Value {
+        Value::from_bits(if val { TRUE_VALUE } else { FALSE_VALUE })
+    }
+
+    pub fn number(val: f64) -> Value {
+        let bits = val.to_bits();
+        if bits & QUIET_NAN == QUIET_NAN {
+            // A NaN with a payload that would look like another type
+            let nan = if bits & SIGN_BIT == 0 { f64::NAN } else { -f64::NAN };
+            return Value::from_bits(nan.to_bits());
+        }
+        Value::from_bits(bits)
+    }
+
+    pub fn object(obj: Rc<LoxObject>) -> Value {
+        let pointer = Rc::into_raw(obj) as u64;
+        assert!(pointer & (SIGN_BIT | QUIET_NAN) == 0, "Pointer {:#x} overlaps the tag bits", pointer);
+        Value::from_bits(SIGN_BIT | QUIET_NAN | pointer)
+    }
+
+    pub fn string(val: String) -> Value {
+        Value::object(Rc::new(LoxObject::String(val)))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.bits == NIL_VALUE
+    }
+
+    pub fn is_bool(&self) -> bool {
+        self.bits == TRUE_VALUE || self.bits == FALSE_VALUE
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match self.bits {
+            TRUE_VALUE => true,
+            FALSE_VALUE => false,
+            _ => panic!("Value is not a bool"),
+        }
+    }
+
+    pub fn as_number(&self) -> f64 {
+        if !self.is_number() {
+            panic!("Value is not a number");
+        }
+        f64::from_bits(self.bits)
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.bits & QUIET_NAN != QUIET_NAN
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.bits & (SIGN_BIT | QUIET_NAN) == SIGN_BIT | QUIET_NAN
+    }
+
+    pub fn as_object(&self) -> &LoxObject {
+        if !self.is_object() {
+            panic!("Value is not an object");
+        }
+        // The pointer is valid while this value holds a reference to the object
+        unsafe { &*self.object_pointer() }
+    }
+
+    pub fn is_string(&self) -> bool {
+        if !self.is_object() {
+            return false;
+        }
+        match *self.as_object() {
+            LoxObject::String(_) => true,
+        }
+    }
+
+    pub fn as_string(&self) -> String {
+        match *self.as_object() {
+            LoxObject::String(ref s) => s.to_string(),
+        }
+    }
+
+    fn object_pointer(&self) -> *const LoxObject {
+        (self.bits & !(SIGN_BIT | QUIET_NAN)) as *const LoxObject
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Value {
+        if self.is_object() {
+            let obj = unsafe { Rc::from_raw(self.object_pointer()) };
+            // Take another reference without dropping the one this value holds
+            mem::forget(Rc::clone(&obj));
+            mem::forget(obj);
+        }
+        Value::from_bits(self.bits)
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        if self.is_object() {
+            drop(unsafe { Rc::from_raw(self.object_pointer()) });
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Value({})", self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(mem::size_of::<Value>(), 8);
+    }
+
+    #[test]
+    fn test_nan_is_a_number() {
+        let nan = Value::number(0.0 / 0.0);
+        assert!(nan.is_number());
+        assert!(nan.as_number().is_nan());
+
+        let payload = Value::number(f64::from_bits(NIL_VALUE));
+        assert!(payload.is_number() && !payload.is_nil());
+        assert!(payload.as_number().is_nan());
+        let negative_payload = Value::number(f64::from_bits(SIGN_BIT | QUIET_NAN | 8));
+        assert!(negative_payload.is_number() && !negative_payload.is_object());
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        let value = Value::number(-0.0);
+        assert!(value.is_number() && !value.is_object());
+        assert!(value.as_number().is_sign_negative());
+        assert_eq!(Value::number(f64::NEG_INFINITY).as_number(), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_object_reference_counts() {
+        let obj = Rc::new(LoxObject::String("abc".to_string()));
+        let value = Value::object(Rc::clone(&obj));
+        assert_eq!(Rc::strong_count(&obj), 2);
+
+        let cloned = value.clone();
+        assert_eq!(Rc::strong_count(&obj), 3);
+        drop(value);
+        assert_eq!(Rc::strong_count(&obj), 2);
+        assert_eq!(cloned.as_string(), "abc");
+        drop(cloned);
+        assert_eq!(Rc::strong_count(&obj), 1);
+    }
+}
@@ -0,0 +1,212 @@
+use ::ast::{BinaryOperator, Expr, UnaryOperator};
+use ::errors::{InterpretError, InterpretResult};
+use ::scanner::{Scanner, Token, TokenType};
+
+/// Parse source code into an expression tree. This is a plain
+/// recursive descent parser following the grammar in Crafting
+/// Interpreters, independent of the single pass compiler, so that
+/// the tree walking interpreter can be used as a reference.
+///
+/// ```text
+/// expression → equality
+/// equality   → comparison ( ( "!=" | "==" ) comparison )*
+/// comparison → term ( ( ">" | ">=" | "<" | "<=" ) term )*
+/// term       → factor ( ( "-" | "+" ) factor )*
+/// factor     → unary ( ( "/" | "*" ) unary )*
+/// unary      → ( "!" | "-" ) unary | primary
+/// primary    → NUMBER | STRING | "true" | "false" | "nil" | "(" expression ")"
+/// ```
+///
+/// Only the first error is reported.
+pub fn parse(source: &str) -> InterpretResult<Expr> {
+    let mut scanner = Scanner::new(source);
+    let current = scanner.scan_token();
+    let mut parser = Parser { scanner, current };
+    if parser.current.token_type == TokenType::Error {
+        return Err(parser.error_at(&parser.current, parser.current.source));
+    }
+    let expr = parser.expression()?;
+    if parser.current.token_type != TokenType::Eof {
+        return Err(parser.error("Expected end of expression"));
+    }
+    Ok(expr)
+}
+
+struct Parser<'s> {
+    scanner: Scanner<'s>,
+    current: Token<'s>,
+}
+
+impl <'s> Parser<'s> {
+    fn expression(&mut self) -> InterpretResult<Expr> {
+        self.equality()
+    }
+
+    fn equality(&mut self) -> InterpretResult<Expr> {
+        let mut expr = self.comparison()?;
+        while let Some(operator) = self.binary_operator(&[BinaryOperator::Equal, BinaryOperator::NotEqual]) {
+            let line = self.advance()?.line;
+            let right = self.comparison()?;
+            expr = binary(operator, expr, right, line);
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> InterpretResult<Expr> {
+        let mut expr = self.term()?;
+        let operators = [
+            BinaryOperator::Greater, BinaryOperator::GreaterEqual,
+            BinaryOperator::Less, BinaryOperator::LessEqual,
+        ];
+        while let Some(operator) = self.binary_operator(&operators) {
+            let line = self.advance()?.line;
+            let right = self.term()?;
+            expr = binary(operator, expr, right, line);
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> InterpretResult<Expr> {
+        let mut expr = self.factor()?;
+        while let Some(operator) = self.binary_operator(&[BinaryOperator::Add, BinaryOperator::Subtract]) {
+            let line = self.advance()?.line;
+            let right = self.factor()?;
+            expr = binary(operator, expr, right, line);
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> InterpretResult<Expr> {
+        let mut expr = self.unary()?;
+        while let Some(operator) = self.binary_operator(&[BinaryOperator::Multiply, BinaryOperator::Divide]) {
+            let line = self.advance()?.line;
+            let right = self.unary()?;
+            expr = binary(operator, expr, right, line);
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> InterpretResult<Expr> {
+        let operator = match self.current.token_type {
+            TokenType::Bang => UnaryOperator::Not,
+            TokenType::Minus => UnaryOperator::Negate,
+            _ => return self.primary(),
+        };
+        let line = self.advance()?.line;
+        let operand = self.unary()?;
+        Ok(Expr::Unary { operator, operand: Box::new(operand), line })
+    }
+
+    fn primary(&mut self) -> InterpretResult<Expr> {
+        let expr = match self.current.token_type {
+            TokenType::Nil => Expr::Nil,
+            TokenType::True => Expr::Bool(true),
+            TokenType::False => Expr::Bool(false),
+            TokenType::Number => {
+                let number = self.current.source.parse()
+                    .map_err(|_| self.error("Invalid number"))?;
+                Expr::Number(number)
+            },
+            TokenType::String => {
+                let source = self.current.source;
+                Expr::String(source[1..source.len() - 1].to_string())
+            },
+            TokenType::LeftParen => {
+                self.advance()?;
+                let expr = self.expression()?;
+                if self.current.token_type != TokenType::RightParen {
+                    return Err(self.error("Expected ')' after expression"));
+                }
+                expr
+            },
+            _ => return Err(self.error("Expected expression")),
+        };
+        self.advance()?;
+        Ok(expr)
+    }
+
+    /// The operator for the current token, if it's one of the given operators
+    fn binary_operator(&self, operators: &[BinaryOperator]) -> Option<BinaryOperator> {
+        let operator = match self.current.token_type {
+            TokenType::Plus => BinaryOperator::Add,
+            TokenType::Minus => BinaryOperator::Subtract,
+            TokenType::Star => BinaryOperator::Multiply,
+            TokenType::Slash => BinaryOperator::Divide,
+            TokenType::EqualEqual => BinaryOperator::Equal,
+            TokenType::BangEqual => BinaryOperator::NotEqual,
+            TokenType::Greater => BinaryOperator::Greater,
+            TokenType::GreaterEqual => BinaryOperator::GreaterEqual,
+            TokenType::Less => BinaryOperator::Less,
+            TokenType::LessEqual => BinaryOperator::LessEqual,
+            _ => return None,
+        };
+        if operators.contains(&operator) { Some(operator) } else { None }
+    }
+
+    /// Move to the next token, returning the one that was current
+    fn advance(&mut self) -> InterpretResult<Token<'s>> {
+        let next = self.scanner.scan_token();
+        if next.token_type == TokenType::Error {
+            return Err(self.error_at(&next, next.source));
+        }
+        Ok(std::mem::replace(&mut self.current, next))
+    }
+
+    fn error(&self, message: &str) -> InterpretError {
+        self.error_at(&self.current, message)
+    }
+
+    fn error_at(&self, token: &Token, message: &str) -> InterpretError {
+        let location = match token.token_type {
+            TokenType::Eof => " at end".to_string(),
+            TokenType::Error => "".to_string(),
+            _ => format!(" at '{}'", token.source),
+        };
+        InterpretError::CompileError(format!("[line {}] Error{}: {}", token.line, location, message))
+    }
+}
+
+fn binary(operator: BinaryOperator, left: Expr, right: Expr, line: usize) -> Expr {
+    Expr::Binary { operator, left: Box::new(left), right: Box::new(right), line }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_to_string(source: &str) -> String {
+        parse(source).unwrap().to_string()
+    }
+
+    fn parse_error(source: &str) -> String {
+        match parse(source).unwrap_err() {
+            InterpretError::CompileError(message) => message,
+            err => format!("Unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        assert_eq!(parse_to_string("1 + 2 * 3 - 4 / 5"), "(- (+ 1 (* 2 3)) (/ 4 5))");
+        assert_eq!(parse_to_string("1 < 2 == 3 >= 4 != nil"), "(!= (== (< 1 2) (>= 3 4)) nil)");
+        assert_eq!(parse_to_string("!-(1 + 2) * --3"), "(* (! (- (+ 1 2))) (- (- 3)))");
+        assert_eq!(parse_to_string("\"a\" + \"b c\" == true"), "(== (+ \"a\" \"b c\") true)");
+    }
+
+    #[test]
+    fn test_operator_lines() {
+        match parse("1\n+\n2").unwrap() {
+            Expr::Binary { line, .. } => assert_eq!(line, 2),
+            expr => assert!(false, "Expected binary expression, got {}", expr),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_error("1 +"), "[line 1] Error at end: Expected expression");
+        assert_eq!(parse_error("(1 + 2"), "[line 1] Error at end: Expected ')' after expression");
+        assert_eq!(parse_error("1 2"), "[line 1] Error at '2': Expected end of expression");
+        assert_eq!(parse_error("1 + \"abc"), "[line 1] Error: Unterminated string");
+        assert_eq!(parse_error("\n@"), "[line 2] Error: Unexpected character");
+    }
+}
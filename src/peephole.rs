@@ -0,0 +1,192 @@
+use ::chunk::Chunk;
+use ::errors::{InterpretError, InterpretResult};
+use ::instructions::Instruction;
+use ::value::Value;
+
+/// An instruction in a chunk being rewritten, with constant loads
+/// holding their value so that constants can be rewritten and the
+/// constant pool rebuilt
+#[derive(Debug,Clone)]
+enum Rewritten {
+    Constant(Value),
+    Instruction(Instruction),
+}
+
+/// Rewrite short sequences of instructions in a chunk into
+/// equivalent, shorter sequences:
+///
+/// * `Not Not` after an instruction that pushes a bool is removed
+/// * `Not` after `Equal` or `NotEqual` becomes the opposite comparison
+/// * `Not` after `True` or `False` becomes the opposite literal
+/// * `Negate` of a numeric constant becomes a negated constant
+///
+/// There are no jump or pop instructions yet, so there are no
+/// jump chains or unused constant loads to remove.
+pub fn optimise(chunk: &Chunk) -> InterpretResult<Chunk> {
+    let mut rewritten: Vec<(Rewritten, usize)> = Vec::new();
+
+    for result in chunk.instructions() {
+        let (offset, instruction, line) = result?;
+        let line = line.unwrap_or(0);
+        // Superinstructions are split up so that the constant pool can be rebuilt
+        let instructions = match instruction.split() {
+            Some((load, operation)) => vec![load, operation],
+            None => vec![instruction],
+        };
+        for instruction in instructions {
+            match instruction {
+                Instruction::Constant(_) => {
+                    let index = instruction.constant_index().unwrap();
+                    let value = chunk.constants.get(index)
+                        .cloned()
+                        .ok_or(InterpretError::BadConstantIndex { offset, index })?;
+                    rewritten.push((Rewritten::Constant(value), line));
+                },
+                _ => rewritten.push((Rewritten::Instruction(instruction), line)),
+            }
+            while rewrite_tail(&mut rewritten) {}
+        }
+    }
+
+    let mut result = Chunk::new();
+    for (rewritten, line) in rewritten {
+        match rewritten {
+            Rewritten::Constant(value) => result.write_constant(value, line)?,
+            Rewritten::Instruction(instruction) => result.write_instruction(instruction, line),
+        }
+    }
+    Ok(result)
+}
+
+/// Apply the first rewrite that matches the end of the instructions,
+/// returning whether anything was changed
+fn rewrite_tail(rewritten: &mut Vec<(Rewritten, usize)>) -> bool {
+    let replacement = match tail(rewritten, 3) {
+        [(Rewritten::Instruction(previous), line),
+         (Rewritten::Instruction(Instruction::Not), _),
+         (Rewritten::Instruction(Instruction::Not), _)] if pushes_bool(*previous) =>
+            Some((3, (Rewritten::Instruction(*previous), *line))),
+        _ => None,
+    };
+    let replacement = replacement.or_else(|| match tail(rewritten, 2) {
+        [(Rewritten::Constant(value), line),
+         (Rewritten::Instruction(Instruction::Negate), _)] if value.is_number() =>
+            Some((2, (Rewritten::Constant(Value::number(-value.as_number())), *line))),
+        [(Rewritten::Instruction(previous), line),
+         (Rewritten::Instruction(Instruction::Not), _)] =>
+            negated(*previous).map(|negated| (2, (Rewritten::Instruction(negated), *line))),
+        _ => None,
+    });
+
+    match replacement {
+        Some((count, instruction)) => {
+            let length = rewritten.len();
+            rewritten.truncate(length - count);
+            rewritten.push(instruction);
+            true
+        },
+        None => false,
+    }
+}
+
+fn tail<T>(values: &[T], count: usize) -> &[T] {
+    if values.len() < count {
+        &[]
+    } else {
+        &values[values.len() - count..]
+    }
+}
+
+/// Whether an instruction always pushes a bool onto the stack
+fn pushes_bool(instruction: Instruction) -> bool {
+    matches!(instruction,
+        Instruction::True | Instruction::False | Instruction::Not
+            | Instruction::Equal | Instruction::NotEqual
+            | Instruction::Greater | Instruction::GreaterEqual
+            | Instruction::Less | Instruction::LessEqual)
+}
+
+/// A single instruction equivalent to the given instruction followed by `Not`.
+/// Ordered comparisons can't be negated this way as they are false for NaN.
+fn negated(instruction: Instruction) -> Option<Instruction> {
+    match instruction {
+        Instruction::Equal => Some(Instruction::NotEqual),
+        Instruction::NotEqual => Some(Instruction::Equal),
+        Instruction::True => Some(Instruction::False),
+        Instruction::False => Some(Instruction::True),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+
+    fn optimised_instructions(source: &str) -> Vec<Instruction> {
+        optimised(source).decoded_instructions()
+    }
+
+    fn optimised(source: &str) -> Chunk {
+        optimise(&compiler::compile_unoptimised(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_remove_double_not_of_bool() {
+        let instructions = optimised_instructions("!!!!(-nil < 2)");
+        assert_eq!(instructions, vec![
+            Instruction::Nil,
+            Instruction::Negate,
+            Instruction::Constant(0),
+            Instruction::Less,
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn test_keep_double_not_of_other_values() {
+        let instructions = optimised_instructions("!!-nil");
+        assert_eq!(instructions, vec![
+            Instruction::Nil,
+            Instruction::Negate,
+            Instruction::Not,
+            Instruction::Not,
+            Instruction::Return,
+        ]);
+    }
+
+    #[test]
+    fn test_negate_comparisons() {
+        let instructions = optimised_instructions("!(-nil == 1) == !(2 != -nil)");
+        assert_eq!(instructions[3], Instruction::NotEqual);
+        assert_eq!(instructions[7], Instruction::Equal);
+        assert_eq!(instructions.len(), 10);
+
+        let instructions = optimised_instructions("!(-nil < 1)");
+        assert_eq!(instructions[3..], [Instruction::Less, Instruction::Not, Instruction::Return]);
+    }
+
+    #[test]
+    fn test_negate_constant() {
+        let chunk = optimised("-nil + -2 - --3");
+        assert_eq!(chunk.decoded_instructions(), vec![
+            Instruction::Nil,
+            Instruction::Negate,
+            Instruction::Constant(0),
+            Instruction::Add,
+            Instruction::Constant(1),
+            Instruction::Subtract,
+            Instruction::Return,
+        ]);
+        let constants: Vec<String> = chunk.constants.iter().map(|c| c.to_string()).collect();
+        assert_eq!(constants, vec!["Number(-2)", "Number(3)"]);
+    }
+
+    #[test]
+    fn test_lines_are_kept() {
+        let chunk = compiler::compile_unoptimised("-nil ==\n!!(1\n< 2)").unwrap();
+        let chunk = optimise(&chunk).unwrap();
+        let lines: Vec<Option<usize>> = chunk.instructions().map(|result| result.unwrap().2).collect();
+        assert_eq!(lines, vec![Some(1), Some(1), Some(2), Some(3), Some(3), Some(3), Some(3)]);
+    }
+}
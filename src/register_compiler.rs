@@ -0,0 +1,224 @@
+use ::chunk::Chunk;
+use ::errors::{InterpretError, InterpretResult};
+use ::instructions::Instruction;
+use ::register_instructions::{Operand, RegisterChunk, RegisterInstruction};
+
+/// Translate a chunk compiled for the stack machine to register
+/// machine code. Constant loads don't emit an instruction, instead the
+/// instruction that would pop the constant reads it directly from the
+/// constants table. Other stack slots are given registers in order,
+/// so a value with n other values in registers below it on the stack
+/// is kept in register n, and constants don't use up any registers.
+pub fn translate(chunk: &Chunk) -> InterpretResult<RegisterChunk> {
+    let mut translator = Translator {
+        chunk: RegisterChunk::new(),
+        stack: Vec::new(),
+        live_registers: 0,
+    };
+    translator.chunk.constants = chunk.constants.clone();
+    translator.chunk.end_offset = chunk.code.len();
+
+    for decoded in chunk.instructions() {
+        let (offset, instruction, line) = decoded?;
+        let line = line.unwrap_or(0);
+        match instruction.split() {
+            Some((load, operation)) => {
+                translator.translate(offset, load, line)?;
+                translator.translate(offset, operation, line)?;
+            },
+            None => translator.translate(offset, instruction, line)?,
+        }
+    }
+    Ok(translator.chunk)
+}
+
+struct Translator {
+    chunk: RegisterChunk,
+    /// Operands holding the values that would be on the stack
+    stack: Vec<Operand>,
+    /// How many of the operands on the stack are registers
+    live_registers: usize,
+}
+
+impl Translator {
+    fn translate(&mut self, offset: usize, instruction: Instruction, line: usize) -> InterpretResult<()> {
+        let register_instruction = match instruction {
+            Instruction::Constant(index) => {
+                if index as usize >= self.chunk.constants.len() {
+                    return Err(InterpretError::BadConstantIndex { offset, index: index as usize });
+                }
+                self.stack.push(Operand::Constant(index));
+                return Ok(());
+            },
+            Instruction::Nil => RegisterInstruction::LoadNil { dest: self.push()? },
+            Instruction::True => RegisterInstruction::LoadBool { dest: self.push()?, value: true },
+            Instruction::False => RegisterInstruction::LoadBool { dest: self.push()?, value: false },
+            Instruction::Negate => {
+                let operand = self.pop(offset)?;
+                RegisterInstruction::Negate { dest: self.push()?, operand }
+            },
+            Instruction::Not => {
+                let operand = self.pop(offset)?;
+                RegisterInstruction::Not { dest: self.push()?, operand }
+            },
+            Instruction::Return => RegisterInstruction::Return { operand: self.pop(offset)? },
+            Instruction::Add | Instruction::Subtract | Instruction::Multiply | Instruction::Divide
+                | Instruction::Equal | Instruction::NotEqual | Instruction::Greater
+                | Instruction::Less | Instruction::GreaterEqual | Instruction::LessEqual => {
+                let right = self.pop(offset)?;
+                let left = self.pop(offset)?;
+                let dest = self.push()?;
+                match instruction {
+                    Instruction::Add => RegisterInstruction::Add { dest, left, right },
+                    Instruction::Subtract => RegisterInstruction::Subtract { dest, left, right },
+                    Instruction::Multiply => RegisterInstruction::Multiply { dest, left, right },
+                    Instruction::Divide => RegisterInstruction::Divide { dest, left, right },
+                    Instruction::Equal => RegisterInstruction::Equal { dest, left, right },
+                    Instruction::NotEqual => RegisterInstruction::NotEqual { dest, left, right },
+                    Instruction::Greater => RegisterInstruction::Greater { dest, left, right },
+                    Instruction::Less => RegisterInstruction::Less { dest, left, right },
+                    Instruction::GreaterEqual => RegisterInstruction::GreaterEqual { dest, left, right },
+                    _ => RegisterInstruction::LessEqual { dest, left, right },
+                }
+            },
+            Instruction::AddConstant(_) | Instruction::SubtractConstant(_)
+                | Instruction::LessConstant(_) | Instruction::GreaterConstant(_) =>
+                unreachable!("Superinstructions are split before translating"),
+        };
+        self.chunk.write_instruction(register_instruction, offset, line);
+        Ok(())
+    }
+
+    fn pop(&mut self, offset: usize) -> InterpretResult<Operand> {
+        match self.stack.pop() {
+            Some(operand) => {
+                if let Operand::Register(_) = operand {
+                    self.live_registers -= 1;
+                }
+                Ok(operand)
+            },
+            None => Err(InterpretError::StackUnderflow { offset }),
+        }
+    }
+
+    /// Push the next free register, returning its index
+    fn push(&mut self) -> InterpretResult<u16> {
+        let register = self.live_registers;
+        if register > u16::MAX as usize {
+            return Err(InterpretError::CompileError("Expression needs too many registers".to_string()));
+        }
+        self.stack.push(Operand::Register(register as u16));
+        self.live_registers += 1;
+        Ok(register as u16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+
+    fn translate_source(source: &str) -> Vec<String> {
+        let chunk = compiler::compile_unoptimised(source).unwrap();
+        translate(&chunk).unwrap().code().iter()
+            .map(|instruction| instruction.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_constants_are_operands() {
+        assert_eq!(translate_source("1 + 2 * 3"), vec![
+            "Multiply r0 k1 k2",
+            "Add r0 k0 r0",
+            "Return r0",
+        ]);
+        assert_eq!(translate_source("1"), vec!["Return k0"]);
+    }
+
+    #[test]
+    fn test_constants_dont_use_registers() {
+        assert_eq!(translate_source("1 + (2 + (nil + 3))"), vec![
+            "LoadNil r0",
+            "Add r0 r0 k2",
+            "Add r0 k1 r0",
+            "Add r0 k0 r0",
+            "Return r0",
+        ]);
+
+        let source = format!("{}1{}", "1 + (".repeat(300), ")".repeat(300));
+        let chunk = compiler::compile_unoptimised(&source).unwrap();
+        assert_eq!(translate(&chunk).unwrap().register_count(), 1);
+    }
+
+    #[test]
+    fn test_literals_are_loaded() {
+        assert_eq!(translate_source("!(nil == false)"), vec![
+            "LoadNil r0",
+            "LoadBool r1 false",
+            "Equal r0 r0 r1",
+            "Not r0 r0",
+            "Return r0",
+        ]);
+    }
+
+    #[test]
+    fn test_split_superinstructions() {
+        let chunk = compiler::compile("-(true) + 2").unwrap();
+        let code: Vec<String> = translate(&chunk).unwrap().code().iter()
+            .map(|instruction| instruction.to_string())
+            .collect();
+        assert_eq!(code, vec!["LoadBool r0 true", "Negate r0 r0", "Add r0 r0 k0", "Return r0"]);
+    }
+
+    #[test]
+    fn test_lines_are_kept() {
+        let chunk = compiler::compile_unoptimised("-true ==\n\nnil").unwrap();
+        let register_chunk = translate(&chunk).unwrap();
+        let lines: Vec<&usize> = register_chunk.lines.into_iter().collect();
+        assert_eq!(lines, vec![&1, &1, &3, &3, &3]);
+    }
+
+    #[test]
+    fn test_offsets_are_kept() {
+        let chunk = compiler::compile_unoptimised("-true ==\n\nnil").unwrap();
+        let register_chunk = translate(&chunk).unwrap();
+        let offsets: Vec<usize> = (0..register_chunk.code().len() + 1)
+            .map(|index| register_chunk.offset(index))
+            .collect();
+        assert_eq!(offsets, vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut chunk = Chunk::new();
+        chunk.write_instruction(Instruction::Nil, 1);
+        chunk.write_instruction(Instruction::Add, 1);
+        match translate(&chunk) {
+            Err(InterpretError::StackUnderflow { offset: 1 }) => {},
+            _ => assert!(false, "Expected stack underflow"),
+        }
+    }
+
+    #[test]
+    fn test_bad_constant_index() {
+        let mut chunk = Chunk::new();
+        chunk.write_instruction(Instruction::Constant(0), 1);
+        chunk.write_instruction(Instruction::Return, 1);
+        match translate(&chunk) {
+            Err(InterpretError::BadConstantIndex { offset: 0, index: 0 }) => {},
+            _ => assert!(false, "Expected bad constant index"),
+        }
+    }
+
+    #[test]
+    fn test_too_many_registers() {
+        let mut chunk = Chunk::new();
+        for _ in 0..=u16::MAX as usize + 1 {
+            chunk.write_instruction(Instruction::Nil, 1);
+        }
+        match translate(&chunk) {
+            Err(InterpretError::CompileError(_)) => {},
+            _ => assert!(false, "Expected compile error"),
+        }
+    }
+}
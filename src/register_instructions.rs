@@ -0,0 +1,193 @@
+use std::fmt;
+
+use ::run_length_encoding::RunLengthEncoded;
+use ::value::Value;
+
+/// A source of a value used by a register instruction. Constants are
+/// read straight from the constants table, so they don't need to be
+/// loaded into a register first.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum Operand {
+    Register(u16),
+    Constant(u32),
+}
+
+/// An instruction for the register machine. Each instruction reads
+/// its operands and writes its result to a destination register,
+/// rather than popping and pushing values on a stack.
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum RegisterInstruction {
+    LoadNil { dest: u16 },
+    LoadBool { dest: u16, value: bool },
+    Negate { dest: u16, operand: Operand },
+    Not { dest: u16, operand: Operand },
+    Add { dest: u16, left: Operand, right: Operand },
+    Subtract { dest: u16, left: Operand, right: Operand },
+    Multiply { dest: u16, left: Operand, right: Operand },
+    Divide { dest: u16, left: Operand, right: Operand },
+    Equal { dest: u16, left: Operand, right: Operand },
+    NotEqual { dest: u16, left: Operand, right: Operand },
+    Greater { dest: u16, left: Operand, right: Operand },
+    Less { dest: u16, left: Operand, right: Operand },
+    GreaterEqual { dest: u16, left: Operand, right: Operand },
+    LessEqual { dest: u16, left: Operand, right: Operand },
+    Return { operand: Operand },
+}
+
+/// Register instructions along with the constants they use and
+/// the line number of each instruction
+pub struct RegisterChunk {
+    code: Vec<RegisterInstruction>,
+    /// Byte offset in the stack machine chunk that each instruction
+    /// was translated from, so errors report the same offsets
+    offsets: Vec<usize>,
+    /// Byte offset reported when running off the end of the code
+    pub end_offset: usize,
+    pub constants: Vec<Value>,
+    pub lines: RunLengthEncoded<usize>,
+    /// One more than the highest register used by any instruction,
+    /// so the machine can allocate every register before running
+    register_count: usize,
+}
+
+impl Default for RegisterChunk {
+    fn default() -> RegisterChunk {
+        RegisterChunk::new()
+    }
+}
+
+impl RegisterChunk {
+    pub fn new() -> RegisterChunk {
+        RegisterChunk {
+            code: Vec::new(),
+            offsets: Vec::new(),
+            end_offset: 0,
+            constants: Vec::new(),
+            lines: RunLengthEncoded::new(),
+            register_count: 0,
+        }
+    }
+
+    pub fn write_instruction(&mut self, instruction: RegisterInstruction, offset: usize, line: usize) {
+        if let Some(register) = instruction.max_register() {
+            self.register_count = self.register_count.max(register as usize + 1);
+        }
+        self.code.push(instruction);
+        self.offsets.push(offset);
+        self.lines.push(line);
+    }
+
+    pub fn code(&self) -> &[RegisterInstruction] {
+        &self.code
+    }
+
+    /// The source byte offset of the instruction at an index,
+    /// or the end offset if the index is past the end of the code
+    pub fn offset(&self, index: usize) -> usize {
+        self.offsets.get(index).cloned().unwrap_or(self.end_offset)
+    }
+
+    pub fn register_count(&self) -> usize {
+        self.register_count
+    }
+}
+
+impl RegisterInstruction {
+    /// The destination and source operands
+    fn operands(&self) -> (Option<u16>, [Option<Operand>; 2]) {
+        match *self {
+            RegisterInstruction::LoadNil { dest } | RegisterInstruction::LoadBool { dest, .. } =>
+                (Some(dest), [None, None]),
+            RegisterInstruction::Negate { dest, operand } | RegisterInstruction::Not { dest, operand } =>
+                (Some(dest), [Some(operand), None]),
+            RegisterInstruction::Add { dest, left, right }
+                | RegisterInstruction::Subtract { dest, left, right }
+                | RegisterInstruction::Multiply { dest, left, right }
+                | RegisterInstruction::Divide { dest, left, right }
+                | RegisterInstruction::Equal { dest, left, right }
+                | RegisterInstruction::NotEqual { dest, left, right }
+                | RegisterInstruction::Greater { dest, left, right }
+                | RegisterInstruction::Less { dest, left, right }
+                | RegisterInstruction::GreaterEqual { dest, left, right }
+                | RegisterInstruction::LessEqual { dest, left, right } =>
+                (Some(dest), [Some(left), Some(right)]),
+            RegisterInstruction::Return { operand } => (None, [Some(operand), None]),
+        }
+    }
+
+    /// The highest register the instruction reads or writes, if any
+    fn max_register(&self) -> Option<u16> {
+        let (dest, sources) = self.operands();
+        let sources = sources.iter().filter_map(|operand| match *operand {
+            Some(Operand::Register(register)) => Some(register),
+            _ => None,
+        });
+        dest.into_iter().chain(sources).max()
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(register) => write!(f, "r{}", register),
+            Operand::Constant(index) => write!(f, "k{}", index),
+        }
+    }
+}
+
+impl fmt::Display for RegisterInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, dest, operands) = match *self {
+            RegisterInstruction::LoadNil { dest } => return write!(f, "LoadNil r{}", dest),
+            RegisterInstruction::LoadBool { dest, value } => return write!(f, "LoadBool r{} {}", dest, value),
+            RegisterInstruction::Return { operand } => return write!(f, "Return {}", operand),
+            RegisterInstruction::Negate { dest, operand } => ("Negate", dest, vec![operand]),
+            RegisterInstruction::Not { dest, operand } => ("Not", dest, vec![operand]),
+            RegisterInstruction::Add { dest, left, right } => ("Add", dest, vec![left, right]),
+            RegisterInstruction::Subtract { dest, left, right } => ("Subtract", dest, vec![left, right]),
+            RegisterInstruction::Multiply { dest, left, right } => ("Multiply", dest, vec![left, right]),
+            RegisterInstruction::Divide { dest, left, right } => ("Divide", dest, vec![left, right]),
+            RegisterInstruction::Equal { dest, left, right } => ("Equal", dest, vec![left, right]),
+            RegisterInstruction::NotEqual { dest, left, right } => ("NotEqual", dest, vec![left, right]),
+            RegisterInstruction::Greater { dest, left, right } => ("Greater", dest, vec![left, right]),
+            RegisterInstruction::Less { dest, left, right } => ("Less", dest, vec![left, right]),
+            RegisterInstruction::GreaterEqual { dest, left, right } => ("GreaterEqual", dest, vec![left, right]),
+            RegisterInstruction::LessEqual { dest, left, right } => ("LessEqual", dest, vec![left, right]),
+        };
+        write!(f, "{} r{}", name, dest)?;
+        for operand in operands {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let instructions = vec![
+            RegisterInstruction::LoadBool { dest: 0, value: true },
+            RegisterInstruction::Not { dest: 0, operand: Operand::Register(0) },
+            RegisterInstruction::Add { dest: 1, left: Operand::Register(1), right: Operand::Constant(3) },
+            RegisterInstruction::Return { operand: Operand::Constant(0) },
+        ];
+        let listing: Vec<String> = instructions.iter().map(|instruction| instruction.to_string()).collect();
+        assert_eq!(listing, vec!["LoadBool r0 true", "Not r0 r0", "Add r1 r1 k3", "Return k0"]);
+    }
+
+    #[test]
+    fn test_register_count() {
+        let mut chunk = RegisterChunk::new();
+        assert_eq!(chunk.register_count(), 0);
+        chunk.write_instruction(RegisterInstruction::Return { operand: Operand::Constant(7) }, 0, 1);
+        assert_eq!(chunk.register_count(), 0);
+        chunk.write_instruction(RegisterInstruction::LoadNil { dest: 2 }, 1, 1);
+        assert_eq!(chunk.register_count(), 3);
+        let add = RegisterInstruction::Add { dest: 0, left: Operand::Register(300), right: Operand::Constant(0) };
+        chunk.write_instruction(add, 2, 1);
+        assert_eq!(chunk.register_count(), 301);
+    }
+}
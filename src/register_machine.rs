@@ -0,0 +1,275 @@
+use ::errors::{InterpretError, InterpretResult, RuntimeErrorDetails, StackTraceFrame};
+use ::register_instructions::{Operand, RegisterChunk, RegisterInstruction};
+use ::value::Value;
+use ::virtual_machine::{is_falsey, values_equal};
+
+/// An alternative to `VirtualMachine` that executes register
+/// instructions, translated from a chunk by `register_compiler`.
+/// It has the same semantics and raises the same runtime errors.
+pub struct RegisterMachine {
+    registers: Vec<Value>,
+    /// Index of the instruction currently being executed
+    instruction_index: usize,
+    source_file: Option<String>,
+}
+
+impl Default for RegisterMachine {
+    fn default() -> RegisterMachine {
+        RegisterMachine::new()
+    }
+}
+
+impl RegisterMachine {
+    pub fn new() -> RegisterMachine {
+        RegisterMachine {
+            registers: Vec::new(),
+            instruction_index: 0,
+            source_file: None,
+        }
+    }
+
+    /// Set the name of the source file the chunk was compiled from,
+    /// for use in runtime error stack traces
+    pub fn set_source_file(&mut self, source_file: Option<&str>) {
+        self.source_file = source_file.map(|s| s.to_string());
+    }
+
+    /// Run a chunk, returning the value from its return instruction.
+    /// Every register the chunk uses is allocated before running it.
+    pub fn interpret(&mut self, chunk: &RegisterChunk) -> InterpretResult<Value> {
+        if self.registers.len() < chunk.register_count() {
+            self.registers.resize(chunk.register_count(), Value::nil());
+        }
+        let result = self.run(chunk);
+        // Don't keep objects alive until the registers are next written
+        self.reset_registers();
+        result
+    }
+
+    fn run(&mut self, chunk: &RegisterChunk) -> InterpretResult<Value> {
+        for (index, instruction) in chunk.code().iter().enumerate() {
+            self.instruction_index = index;
+            let (dest, value) = match *instruction {
+                RegisterInstruction::LoadNil { dest } => (dest, Value::nil()),
+                RegisterInstruction::LoadBool { dest, value } => (dest, Value::bool(value)),
+                RegisterInstruction::Negate { dest, operand } => {
+                    let value = self.operand(chunk, operand)?;
+                    if !value.is_number() {
+                        return self.runtime_error(chunk, "Operand must be a number");
+                    }
+                    (dest, Value::number(-value.as_number()))
+                },
+                RegisterInstruction::Not { dest, operand } => {
+                    let value = self.operand(chunk, operand)?.clone();
+                    (dest, Value::bool(is_falsey(value)))
+                },
+                RegisterInstruction::Add { dest, left, right } => {
+                    (dest, self.add(chunk, left, right)?)
+                },
+                RegisterInstruction::Subtract { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| {a - b}, Value::number)?)
+                },
+                RegisterInstruction::Multiply { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| {a * b}, Value::number)?)
+                },
+                RegisterInstruction::Divide { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| {a / b}, Value::number)?)
+                },
+                RegisterInstruction::Equal { dest, left, right } => {
+                    (dest, Value::bool(self.equal(chunk, left, right)?))
+                },
+                RegisterInstruction::NotEqual { dest, left, right } => {
+                    (dest, Value::bool(!self.equal(chunk, left, right)?))
+                },
+                RegisterInstruction::Greater { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| a > b, Value::bool)?)
+                },
+                RegisterInstruction::Less { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| a < b, Value::bool)?)
+                },
+                RegisterInstruction::GreaterEqual { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| a >= b, Value::bool)?)
+                },
+                RegisterInstruction::LessEqual { dest, left, right } => {
+                    (dest, self.binary_op(chunk, left, right, |a, b| a <= b, Value::bool)?)
+                },
+                RegisterInstruction::Return { operand } => {
+                    return Ok(self.operand(chunk, operand)?.clone());
+                },
+            };
+            self.registers[dest as usize] = value;
+        }
+        // Like the stack machine, running off the end of the code is an error
+        Err(InterpretError::TruncatedInstruction { offset: chunk.end_offset })
+    }
+
+    fn operand<'a>(&'a self, chunk: &'a RegisterChunk, operand: Operand) -> InterpretResult<&'a Value> {
+        match operand {
+            Operand::Register(register) => Ok(&self.registers[register as usize]),
+            Operand::Constant(index) => match chunk.constants.get(index as usize) {
+                Some(value) => Ok(value),
+                None => Err(InterpretError::BadConstantIndex {
+                    offset: chunk.offset(self.instruction_index),
+                    index: index as usize,
+                }),
+            },
+        }
+    }
+
+    fn add(&self, chunk: &RegisterChunk, left: Operand, right: Operand) -> InterpretResult<Value> {
+        let a = self.operand(chunk, left)?;
+        let b = self.operand(chunk, right)?;
+        if a.is_string() && b.is_string() {
+            Ok(Value::string(format!("{}{}", a.as_string(), b.as_string())))
+        }
+        else if a.is_number() && b.is_number() {
+            Ok(Value::number(a.as_number() + b.as_number()))
+        }
+        else {
+            self.runtime_error(chunk, "Operands must be two numbers or two strings")
+        }
+    }
+
+    fn binary_op<F, FC, T>(&self, chunk: &RegisterChunk, left: Operand, right: Operand,
+                           binary_fn: F, value_creator: FC) -> InterpretResult<Value>
+        where F: Fn(f64, f64) -> T, FC: Fn(T) -> Value
+    {
+        let a = self.operand(chunk, left)?;
+        let b = self.operand(chunk, right)?;
+        if !(a.is_number() && b.is_number()) {
+            return self.runtime_error(chunk, "Operands must be numbers");
+        }
+        Ok(value_creator(binary_fn(a.as_number(), b.as_number())))
+    }
+
+    fn equal(&self, chunk: &RegisterChunk, left: Operand, right: Operand) -> InterpretResult<bool> {
+        let a = self.operand(chunk, left)?.clone();
+        let b = self.operand(chunk, right)?.clone();
+        Ok(values_equal(a, b))
+    }
+
+    fn reset_registers(&mut self) {
+        for register in self.registers.iter_mut() {
+            *register = Value::nil();
+        }
+    }
+
+    fn runtime_error<T>(&self, chunk: &RegisterChunk, message: &str) -> InterpretResult<T> {
+        let line = chunk.lines.get(self.instruction_index).cloned().unwrap_or(0);
+        Err(InterpretError::RuntimeError(RuntimeErrorDetails {
+            message: message.to_string(),
+            stack_trace: vec![StackTraceFrame {
+                function_name: "script".to_string(),
+                source_file: self.source_file.clone(),
+                line,
+                column: None,
+            }],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler::{self, OptimisationLevel};
+    use ::register_compiler;
+    use ::register_instructions::{Operand, RegisterChunk, RegisterInstruction};
+    use ::virtual_machine::VirtualMachine;
+
+    /// Run a source file on both backends, returning the
+    /// result or error from each formatted as a string
+    fn run_both(source: &str, optimisation: OptimisationLevel) -> (String, String) {
+        let chunk = match compiler::compile_with_optimisation(source, optimisation) {
+            Ok(chunk) => chunk,
+            Err(err) => return (err.to_string(), err.to_string()),
+        };
+        let mut vm = VirtualMachine::new();
+        vm.set_source_file(Some("test.lox"));
+        let stack_result = match vm.interpret(&chunk) {
+            Ok(value) => value.to_string(),
+            Err(err) => err.to_string(),
+        };
+
+        let register_chunk = register_compiler::translate(&chunk).unwrap();
+        let mut machine = RegisterMachine::new();
+        machine.set_source_file(Some("test.lox"));
+        let register_result = match machine.interpret(&register_chunk) {
+            Ok(value) => value.to_string(),
+            Err(err) => err.to_string(),
+        };
+        (stack_result, register_result)
+    }
+
+    fn assert_same_result(source: &str) {
+        for &optimisation in &[OptimisationLevel::None, OptimisationLevel::Basic] {
+            let (stack_result, register_result) = run_both(source, optimisation);
+            assert_eq!(register_result, stack_result, "Running '{}' with {:?}", source, optimisation);
+        }
+    }
+
+    #[test]
+    fn test_matches_stack_machine() {
+        let sources = vec![
+            "1 + 2 * 3 - 4 / 5",
+            "-(1.5 - -2)",
+            "!(5 - 4 > 3 * 2 == !nil)",
+            "\"a\" + \"b\" + \"c\" == \"abc\"",
+            "1 != 2",
+            "0 / 0 >= 1",
+            "0 / 0 <= 1",
+            "(0 / 0) != (0 / 0)",
+            "nil == false",
+            "true == !false",
+            "1 +\n\"a\"",
+            "1 <\nnil",
+            "-\"a\"",
+            "\"a\" + 1",
+        ];
+        for source in sources {
+            assert_same_result(source);
+        }
+    }
+
+    #[test]
+    fn test_runtime_error_line() {
+        let chunk = compiler::compile("1 +\n\n-true").unwrap();
+        let register_chunk = register_compiler::translate(&chunk).unwrap();
+
+        match RegisterMachine::new().interpret(&register_chunk) {
+            Err(InterpretError::RuntimeError(details)) => {
+                assert_eq!(details.message, "Operand must be a number");
+                assert_eq!(details.stack_trace[0].line, 3);
+            },
+            result => assert!(false, "Expected runtime error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_errors_report_source_offsets() {
+        let mut chunk = RegisterChunk::new();
+        chunk.write_instruction(RegisterInstruction::LoadNil { dest: 0 }, 0, 1);
+        chunk.write_instruction(RegisterInstruction::Not { dest: 0, operand: Operand::Register(0) }, 1, 1);
+        chunk.end_offset = 2;
+        match RegisterMachine::new().interpret(&chunk) {
+            Err(InterpretError::TruncatedInstruction { offset: 2 }) => {},
+            result => assert!(false, "Expected truncated instruction, got {:?}", result),
+        }
+
+        let add = RegisterInstruction::Add { dest: 0, left: Operand::Register(0), right: Operand::Constant(0) };
+        chunk.write_instruction(add, 4, 1);
+        match RegisterMachine::new().interpret(&chunk) {
+            Err(InterpretError::BadConstantIndex { offset: 4, index: 0 }) => {},
+            result => assert!(false, "Expected bad constant index, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_registers_are_reset() {
+        let chunk = compiler::compile_unoptimised("(\"a\" + \"b\") == \"c\"").unwrap();
+        let register_chunk = register_compiler::translate(&chunk).unwrap();
+        let mut machine = RegisterMachine::new();
+
+        assert!(!machine.interpret(&register_chunk).unwrap().as_bool());
+        assert!(machine.registers.iter().all(|register| register.is_nil()));
+    }
+}
@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+use rustyline::{Context, Editor, Helper};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+
+use ::compiler;
+use ::debug;
+use ::errors::{InterpretError, InterpretResult};
+use ::loader::{self, LoadOptions};
+use ::scanner::{Scanner, TokenType, KEYWORDS, UNTERMINATED_STRING};
+use ::trace::{TraceOptions, Tracer};
+use ::value::Value;
+use ::virtual_machine::VirtualMachine;
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = ". ";
+const HISTORY_FILE_NAME: &str = ".rlox_history";
+
+const HELP: &str = "\
+Enter an expression to evaluate it, or one of the following commands:
+  :dis <expr>      Show the compiled bytecode for an expression
+  :load <file>     Run a source, bytecode or assembly file in the current session
+  :globals         List the defined global variables
+  :reset           Start a fresh session
+  :time <expr>     Evaluate an expression and show how long it took
+  :help            Show this help";
+
+/// Provides tab completion of names when editing REPL input,
+/// using the session's VM to find the globals defined so far
+struct ReplHelper {
+    vm: Rc<RefCell<VirtualMachine>>,
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = word_start(line, pos);
+        Ok((start, completion_candidates(&self.vm.borrow(), &line[start..pos])))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+/// Run an interactive session. Expressions and files are compiled
+/// and loaded with the same options as files run from the command line.
+pub fn run_repl(trace_options: Option<TraceOptions>, options: LoadOptions) -> InterpretResult<()> {
+    let vm = Rc::new(RefCell::new(new_vm(&trace_options, options)));
+    let mut editor = Editor::<ReplHelper>::new();
+    editor.set_helper(Some(ReplHelper { vm: vm.clone() }));
+    let history_path = history_path();
+    if let Some(history_path) = &history_path {
+        // There won't be a history file the first time the REPL is run
+        let _ = editor.load_history(history_path);
+    }
+
+    while let Some(source) = read_input(&mut editor)? {
+        if source.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(source.as_str());
+        // The helper borrows the VM while reading input, so only borrow it here
+        let mut vm = vm.borrow_mut();
+        let result = match parse_command(&source) {
+            Some((command, argument)) => run_command(&mut vm, &trace_options, options, command, argument),
+            None => interpret(&mut vm, options, &source).map(|value| println!("{}", value)),
+        };
+        // Report errors but keep the session going
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+    }
+    println!();
+
+    if let Some(history_path) = &history_path {
+        editor.save_history(history_path).map_err(readline_error)?;
+    }
+    Ok(())
+}
+
+/// Split a meta-command input into the command name and its argument
+fn parse_command(source: &str) -> Option<(&str, &str)> {
+    let source = source.trim();
+    if !source.starts_with(':') {
+        return None;
+    }
+    let command = &source[1..];
+    match command.find(char::is_whitespace) {
+        Some(index) => Some((&command[..index], command[index..].trim())),
+        None => Some((command, "")),
+    }
+}
+
+fn new_vm(trace_options: &Option<TraceOptions>, options: LoadOptions) -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    vm.set_tracer(trace_options.clone().map(Tracer::stdout));
+    vm.set_stack_limit(options.stack_limit);
+    vm
+}
+
+fn run_command(
+    vm: &mut VirtualMachine, trace_options: &Option<TraceOptions>, options: LoadOptions,
+    command: &str, argument: &str) -> InterpretResult<()>
+{
+    match command {
+        "dis" => {
+            let chunk = compiler::compile_with_optimisation(argument, options.optimisation)?;
+            debug::disassemble_chunk(&mut io::stdout(), &chunk, argument)?;
+        },
+        "load" => {
+            let chunk = loader::load_file(argument, options)?;
+            vm.set_source_file(Some(argument));
+            let result = vm.interpret(&chunk);
+            vm.set_source_file(None);
+            println!("{}", result?);
+        },
+        "globals" => {
+            let globals = vm.globals();
+            if globals.is_empty() {
+                println!("No globals defined");
+            }
+            for (name, value) in globals {
+                println!("{} = {}", name, value);
+            }
+        },
+        "reset" => {
+            *vm = new_vm(trace_options, options);
+        },
+        "time" => {
+            let start = Instant::now();
+            let value = interpret(vm, options, argument)?;
+            let elapsed = start.elapsed();
+            println!("{}", value);
+            println!("Elapsed: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+        },
+        "help" => {
+            println!("{}", HELP);
+        },
+        _ => {
+            eprintln!("Unknown command ':{}', enter :help for a list of commands", command);
+        },
+    }
+    Ok(())
+}
+
+fn interpret(vm: &mut VirtualMachine, options: LoadOptions, source: &str) -> InterpretResult<Value> {
+    let chunk = compiler::compile_with_optimisation(source, options.optimisation)?;
+    vm.interpret(&chunk)
+}
+
+/// Read a complete input, which may span multiple lines.
+/// Returns None once the end of input is reached.
+fn read_input(editor: &mut Editor<ReplHelper>) -> InterpretResult<Option<String>> {
+    let mut source = String::new();
+    loop {
+        let prompt = if source.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if !source.is_empty() {
+                    source.push('\n');
+                }
+                source.push_str(&line);
+            },
+            Err(ReadlineError::Interrupted) => {
+                // Ctrl-C discards the current input
+                source.clear();
+                continue;
+            },
+            Err(ReadlineError::Eof) => {
+                return Ok(None);
+            },
+            Err(err) => {
+                return Err(readline_error(err));
+            },
+        }
+        if !is_incomplete(&source) {
+            return Ok(Some(source));
+        }
+    }
+}
+
+/// Whether the source has unclosed brackets or strings,
+/// so more input should be read before compiling it
+fn is_incomplete(source: &str) -> bool {
+    let mut scanner = Scanner::new(source);
+    let mut depth = 0;
+    loop {
+        let token = scanner.scan_token();
+        match token.token_type {
+            TokenType::LeftParen | TokenType::LeftBrace => depth += 1,
+            TokenType::RightParen | TokenType::RightBrace => depth -= 1,
+            TokenType::Error if token.source == UNTERMINATED_STRING => return true,
+            TokenType::Eof => return depth > 0,
+            _ => {},
+        }
+    }
+}
+
+/// Find the start of the identifier that ends at the cursor position
+fn word_start(line: &str, pos: usize) -> usize {
+    line[..pos].char_indices()
+        .rev()
+        .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |(index, c)| index + c.len_utf8())
+}
+
+/// Keywords and globals defined in the VM that could complete the given prefix
+fn completion_candidates(vm: &VirtualMachine, prefix: &str) -> Vec<String> {
+    if prefix.is_empty() {
+        return Vec::new();
+    }
+    let keywords = KEYWORDS.iter().map(|&(keyword, _)| keyword);
+    let globals = vm.globals().into_iter().map(|(name, _)| name);
+    let mut candidates: Vec<String> = keywords.chain(globals)
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| name.to_string())
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(HISTORY_FILE_NAME))
+}
+
+fn readline_error(err: ReadlineError) -> InterpretError {
+    match err {
+        ReadlineError::Io(io_error) => InterpretError::IoError(io_error),
+        err => InterpretError::IoError(io::Error::other(err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("1 + 2"), None);
+        assert_eq!(parse_command(":help"), Some(("help", "")));
+        assert_eq!(parse_command(" :dis  1 + 2 "), Some(("dis", "1 + 2")));
+        assert_eq!(parse_command(":load\ttest.lox"), Some(("load", "test.lox")));
+    }
+
+    #[test]
+    fn test_word_start() {
+        assert_eq!(word_start("", 0), 0);
+        assert_eq!(word_start("tr", 2), 0);
+        assert_eq!(word_start("1 + tr", 6), 4);
+        assert_eq!(word_start("!(fal", 5), 2);
+        assert_eq!(word_start("\"é\" + n", 8), 7);
+    }
+
+    #[test]
+    fn test_completion_candidates() {
+        let vm = VirtualMachine::new();
+        assert_eq!(completion_candidates(&vm, "t"), vec!["this", "true"]);
+        assert_eq!(completion_candidates(&vm, "nil"), vec!["nil"]);
+        assert_eq!(completion_candidates(&vm, "x"), Vec::<String>::new());
+        assert_eq!(completion_candidates(&vm, ""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_defined_globals_are_completed() {
+        let vm = Rc::new(RefCell::new(VirtualMachine::new()));
+        let helper = ReplHelper { vm: vm.clone() };
+        let history = rustyline::history::History::new();
+        let context = Context::new(&history);
+        assert_eq!(helper.complete("1 + to", 6, &context).unwrap(), (4, Vec::<String>::new()));
+
+        vm.borrow_mut().define_global("total", Value::number(1.0));
+        vm.borrow_mut().define_global("other", Value::nil());
+        assert_eq!(helper.complete("1 + to", 6, &context).unwrap(), (4, vec!["total".to_string()]));
+        assert_eq!(completion_candidates(&vm.borrow(), "t"), vec!["this", "total", "true"]);
+    }
+
+    #[test]
+    fn test_load_command_uses_file_loader() {
+        let path = std::env::temp_dir().join("rlox_repl_load.lasm");
+        std::fs::write(&path, "1 OpCode::True\nOpCode::Return").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let mut vm = VirtualMachine::new();
+        let result = run_command(&mut vm, &None, LoadOptions::default(), "load", &path);
+        let too_deep = LoadOptions { stack_limit: 0, ..LoadOptions::default() };
+        let limited_result = run_command(&mut vm, &None, too_deep, "load", &path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_ok());
+        match limited_result {
+            Err(InterpretError::StackLimitExceeded { depth: 1, limit: 0 }) => {},
+            _ => assert!(false, "Expected stack limit error"),
+        }
+    }
+
+    #[test]
+    fn test_complete_input() {
+        let test_cases = vec!["1 + 2", "(1 + 2)", "\"abc\"", "", "1 +", ")"];
+        for source in test_cases {
+            assert!(!is_incomplete(source), "Expected '{}' to be complete", source);
+        }
+    }
+
+    #[test]
+    fn test_incomplete_input() {
+        let test_cases = vec!["(1 + 2", "((1 + 2)", "{", "\"abc", "(\"a)\"", "1 + \"abc\n"];
+        for source in test_cases {
+            assert!(is_incomplete(source), "Expected '{}' to be incomplete", source);
+        }
+    }
+}
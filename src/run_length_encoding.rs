@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 pub struct RunLengthEncoded<T> {
     run_lengths: Vec<RunLength<T>>,
 }
@@ -5,6 +7,9 @@ pub struct RunLengthEncoded<T> {
 struct RunLength<T> {
     value: T,
     run_length: usize,
+    /// Index of the first value in this run, so that runs can
+    /// be found by binary search
+    start: usize,
 }
 
 pub struct RunLengthIterator<'a, T: 'a> {
@@ -14,16 +19,27 @@ pub struct RunLengthIterator<'a, T: 'a> {
 }
 
 impl<T> RunLength<T> {
-    pub fn new(value: T, run_length: usize) -> RunLength<T> {
+    pub fn new(value: T, run_length: usize, start: usize) -> RunLength<T> {
         RunLength {
             value,
             run_length,
+            start,
         }
     }
 
     pub fn increment_by(&mut self, increment: usize) {
         self.run_length += increment
     }
+
+    fn end(&self) -> usize {
+        self.start + self.run_length
+    }
+}
+
+impl<T: PartialEq+Clone> Default for RunLengthEncoded<T> {
+    fn default() -> RunLengthEncoded<T> {
+        RunLengthEncoded::new()
+    }
 }
 
 impl<T: PartialEq+Clone> RunLengthEncoded<T> {
@@ -38,6 +54,9 @@ impl<T: PartialEq+Clone> RunLengthEncoded<T> {
     }
 
     pub fn push_run(&mut self, value: T, count: usize) {
+        if count == 0 {
+            return;
+        }
         let continue_run = match self.run_lengths.last() {
             Some(run_length) if run_length.value == value => true,
             _ => false
@@ -45,12 +64,70 @@ impl<T: PartialEq+Clone> RunLengthEncoded<T> {
         if continue_run {
             self.run_lengths.last_mut().unwrap().increment_by(count);
         } else {
-            self.run_lengths.push(RunLength::new(value, count));
+            let start = self.len();
+            self.run_lengths.push(RunLength::new(value, count, start));
         }
     }
 
     pub fn nth(&self, index: usize) -> T {
-        self.into_iter().nth(index).unwrap().clone()
+        self.get(index).unwrap().clone()
+    }
+
+    /// Get the value at an index, in O(log n) time in the number of runs
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.find_run(index).map(|run| &self.run_lengths[run].value)
+    }
+
+    /// The total number of values, including repeats
+    pub fn len(&self) -> usize {
+        self.run_lengths.last().map_or(0, |run| run.end())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.run_lengths.is_empty()
+    }
+
+    /// Iterate over the values in a range of indices
+    pub fn range(&self, range: Range<usize>) -> impl Iterator<Item=&T> {
+        let count = range.end.min(self.len()).saturating_sub(range.start);
+        let iterator = match self.find_run(range.start) {
+            Some(run) => RunLengthIterator {
+                run_length_encoded: self,
+                index: run,
+                run_index: range.start - self.run_lengths[run].start,
+            },
+            None => RunLengthIterator {
+                run_length_encoded: self,
+                index: self.run_lengths.len(),
+                run_index: 0,
+            },
+        };
+        iterator.take(count)
+    }
+
+    /// Iterate over the runs that overlap a range of indices, with
+    /// the run lengths clipped to the range
+    pub fn runs_in_range(&self, range: Range<usize>) -> impl Iterator<Item=(&T, usize)> {
+        let Range { start, end } = range;
+        // An empty range overlaps no runs, even one containing its start
+        let first_run = if start < end { self.find_run(start) } else { None }
+            .unwrap_or(self.run_lengths.len());
+        self.run_lengths[first_run..].iter()
+            .take_while(move |run| run.start < end)
+            .map(move |run| (&run.value, run.end().min(end) - run.start.max(start)))
+    }
+
+    /// Iterate over each run as a value and the number of times it is repeated
+    pub fn runs(&self) -> impl Iterator<Item=(&T, usize)> {
+        self.run_lengths.iter().map(|run| (&run.value, run.run_length))
+    }
+}
+
+impl<T> RunLengthEncoded<T> {
+    /// Find the index of the run containing the value at an index
+    fn find_run(&self, index: usize) -> Option<usize> {
+        let run = self.run_lengths.partition_point(|run| run.end() <= index);
+        if run < self.run_lengths.len() { Some(run) } else { None }
     }
 }
 
@@ -132,6 +209,130 @@ mod test {
         assert_eq!(values.run_lengths[2].value, 7);
     }
 
+    #[test]
+    fn test_runs() {
+        let mut values = RunLengthEncoded::new();
+        values.push(1);
+        values.push(1);
+        values.push_run(2, 3);
+
+        let runs: Vec<(&i32, usize)> = values.runs().collect();
+        assert_eq!(runs, vec![(&1, 2), (&2, 3)]);
+    }
+
+    #[test]
+    fn test_push_empty_run() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 0);
+        values.push(2);
+
+        assert_eq!(values.run_lengths.len(), 1);
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn test_len() {
+        let mut values = RunLengthEncoded::new();
+        assert_eq!(values.len(), 0);
+        assert!(values.is_empty());
+        values.push(1);
+        values.push(1);
+        values.push_run(2, 3);
+        assert_eq!(values.len(), 5);
+        assert!(!values.is_empty());
+    }
+
+    #[test]
+    fn test_get() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 2);
+        values.push(2);
+        values.push_run(7, 3);
+
+        let expected = vec![1, 1, 2, 7, 7, 7];
+        for (index, value) in expected.iter().enumerate() {
+            assert_eq!(values.get(index), Some(value));
+            assert_eq!(values.nth(index), *value);
+        }
+        assert_eq!(values.get(6), None);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 2);
+        values.push(2);
+        values.push_run(7, 3);
+
+        let range: Vec<&i32> = values.range(1..5).collect();
+        assert_eq!(range, vec![&1, &2, &7, &7]);
+        let range: Vec<&i32> = values.range(4..10).collect();
+        assert_eq!(range, vec![&7, &7]);
+        let range: Vec<&i32> = values.range(6..10).collect();
+        assert_eq!(range.len(), 0);
+    }
+
+    #[test]
+    fn test_runs_in_range() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 2);
+        values.push(2);
+        values.push_run(7, 3);
+
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(1..4).collect();
+        assert_eq!(runs, vec![(&1, 1), (&2, 1), (&7, 1)]);
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(0..6).collect();
+        assert_eq!(runs, vec![(&1, 2), (&2, 1), (&7, 3)]);
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(6..8).collect();
+        assert_eq!(runs.len(), 0);
+    }
+
+    #[test]
+    fn test_empty_ranges() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 2);
+        values.push(2);
+        values.push_run(7, 3);
+
+        for &(start, end) in &[(0, 0), (4, 4), (5, 4), (10, 2)] {
+            assert_eq!(values.range(Range { start, end }).count(), 0, "Expected {}..{} to be empty", start, end);
+            assert_eq!(values.runs_in_range(Range { start, end }).count(), 0, "Expected {}..{} to be empty", start, end);
+        }
+        let empty: RunLengthEncoded<i32> = RunLengthEncoded::new();
+        assert_eq!(empty.range(0..2).count(), 0);
+        assert_eq!(empty.runs_in_range(0..2).count(), 0);
+    }
+
+    #[test]
+    fn test_ranges_within_a_run() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 2);
+        values.push(2);
+        values.push_run(7, 3);
+
+        let range: Vec<&i32> = values.range(4..6).collect();
+        assert_eq!(range, vec![&7, &7]);
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(4..6).collect();
+        assert_eq!(runs, vec![(&7, 2)]);
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(2..3).collect();
+        assert_eq!(runs, vec![(&2, 1)]);
+    }
+
+    #[test]
+    fn test_ranges_spanning_run_boundaries() {
+        let mut values = RunLengthEncoded::new();
+        values.push_run(1, 2);
+        values.push(2);
+        values.push_run(7, 3);
+
+        let range: Vec<&i32> = values.range(2..4).collect();
+        assert_eq!(range, vec![&2, &7]);
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(1..3).collect();
+        assert_eq!(runs, vec![(&1, 1), (&2, 1)]);
+        let runs: Vec<(&i32, usize)> = values.runs_in_range(2..4).collect();
+        assert_eq!(runs, vec![(&2, 1), (&7, 1)]);
+    }
+
     #[test]
     fn test_iterate_empty() {
         let values = RunLengthEncoded::new();
@@ -1,3 +1,17 @@
+/// Message of the error token produced when a string isn't closed
+pub const UNTERMINATED_STRING: &str = "Unterminated string";
+
+/// All reserved words and their token types, as recognised by
+/// `Scanner::identifier_type`
+pub const KEYWORDS: [(&str, TokenType); 16] = [
+    ("and", TokenType::And), ("class", TokenType::Class), ("else", TokenType::Else),
+    ("false", TokenType::False), ("for", TokenType::For), ("fun", TokenType::Fun),
+    ("if", TokenType::If), ("nil", TokenType::Nil), ("or", TokenType::Or),
+    ("print", TokenType::Print), ("return", TokenType::Return), ("super", TokenType::Super),
+    ("this", TokenType::This), ("true", TokenType::True), ("var", TokenType::Var),
+    ("while", TokenType::While),
+];
+
 pub struct Scanner<'a> {
     /// Source to be scanned
     source: &'a str,
@@ -201,7 +215,7 @@ impl <'a> Scanner<'a> {
             self.advance();
         }
         if self.is_at_end() {
-            return self.error_token("Unterminated string");
+            return self.error_token(UNTERMINATED_STRING);
         }
         // Skip closing "
         self.advance();
@@ -422,6 +436,24 @@ mod tests {
         test_parse("while", TokenType::While, 5);
     }
 
+    #[test]
+    fn test_keywords_list_matches_scanner() {
+        for &(keyword, token_type) in KEYWORDS.iter() {
+            let mut scanner = Scanner::new(keyword);
+            let token = scanner.scan_token();
+            assert_eq!(
+                token.token_type, token_type,
+                "Expected '{}' to be scanned as a keyword", keyword);
+
+            let identifier = format!("{}_", keyword);
+            let mut scanner = Scanner::new(&identifier);
+            let token = scanner.scan_token();
+            assert_eq!(
+                token.token_type, TokenType::Identifier,
+                "Expected '{}' to be scanned as an identifier", identifier);
+        }
+    }
+
     #[test]
     fn test_parse_identifiers() {
         let test_cases = vec!["and1", "true_", "my_var", "my_var_2"];
@@ -0,0 +1,270 @@
+use std::convert::TryFrom;
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use ::chunk::Chunk;
+use ::errors::{InterpretError, InterpretResult};
+use ::instructions::{read_varint, write_varint};
+use ::object::LoxObject;
+use ::value::Value;
+
+/// Identifies a compiled bytecode file
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// Incremented whenever the file format or instruction set changes
+pub const FORMAT_VERSION: u16 = 5;
+
+// Tags identifying the type of each serialised constant
+const NIL_TAG: u8 = 0;
+const BOOL_TAG: u8 = 1;
+const NUMBER_TAG: u8 = 2;
+const STRING_TAG: u8 = 3;
+
+/// Write a chunk in the binary bytecode format:
+///
+/// * Magic header and format version
+/// * Maximum stack depth
+/// * Constants table, with each value prefixed by a type tag
+/// * Code bytes
+/// * Run-length encoded line numbers
+///
+/// Lengths are written as varints, the same as instruction operands,
+/// and other integers are little-endian.
+pub fn write_chunk<W: Write>(writer: &mut W, chunk: &Chunk) -> InterpretResult<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_u16::<LittleEndian>(FORMAT_VERSION)?;
+    write_length(writer, chunk.max_stack_depth)?;
+
+    write_length(writer, chunk.constants.len())?;
+    for constant in &chunk.constants {
+        write_value(writer, constant)?;
+    }
+
+    write_length(writer, chunk.code.len())?;
+    writer.write_all(&chunk.code)?;
+
+    write_length(writer, chunk.lines.runs().count())?;
+    for (&line, run_length) in chunk.lines.runs() {
+        write_length(writer, line)?;
+        write_length(writer, run_length)?;
+    }
+    Ok(())
+}
+
+/// Read a chunk written by `write_chunk`
+pub fn read_chunk<R: Read>(reader: &mut R) -> InterpretResult<Chunk> {
+    read_chunk_impl(reader).map_err(|err| match err {
+        InterpretError::IoError(ref io_error) if io_error.kind() == io::ErrorKind::UnexpectedEof =>
+            invalid_bytecode("Unexpected end of file"),
+        err => err,
+    })
+}
+
+/// Whether the data starts with the bytecode file header
+pub fn is_bytecode(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn read_chunk_impl<R: Read>(reader: &mut R) -> InterpretResult<Chunk> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_bytecode("Not a bytecode file"));
+    }
+    let version = reader.read_u16::<LittleEndian>()?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_bytecode(&format!(
+            "Unsupported format version {}, expected version {}", version, FORMAT_VERSION)));
+    }
+
+    let mut chunk = Chunk::new();
+    chunk.max_stack_depth = read_length(reader)?;
+
+    let constants_count = read_length(reader)?;
+    for _ in 0..constants_count {
+        let value = read_value(reader)?;
+        chunk.constants.push(value);
+    }
+
+    let code_length = read_length(reader)?;
+    reader.take(code_length as u64).read_to_end(&mut chunk.code)?;
+    if chunk.code.len() != code_length {
+        return Err(invalid_bytecode("Unexpected end of file"));
+    }
+
+    let runs_count = read_length(reader)?;
+    for _ in 0..runs_count {
+        let line = read_length(reader)?;
+        let run_length = read_length(reader)?;
+        if run_length == 0 {
+            return Err(invalid_bytecode("Line run is empty"));
+        }
+        chunk.lines.push_run(line, run_length);
+    }
+    Ok(chunk)
+}
+
+fn write_value<W: Write>(writer: &mut W, value: &Value) -> InterpretResult<()> {
+    if value.is_nil() {
+        writer.write_u8(NIL_TAG)?;
+    } else if value.is_bool() {
+        writer.write_u8(BOOL_TAG)?;
+        writer.write_u8(value.as_bool() as u8)?;
+    } else if value.is_number() {
+        writer.write_u8(NUMBER_TAG)?;
+        writer.write_f64::<LittleEndian>(value.as_number())?;
+    } else {
+        match *value.as_object() {
+            LoxObject::String(ref s) => {
+                writer.write_u8(STRING_TAG)?;
+                write_length(writer, s.len())?;
+                writer.write_all(s.as_bytes())?;
+            },
+        }
+    }
+    Ok(())
+}
+
+fn read_value<R: Read>(reader: &mut R) -> InterpretResult<Value> {
+    let tag = reader.read_u8()?;
+    match tag {
+        NIL_TAG => Ok(Value::nil()),
+        BOOL_TAG => Ok(Value::bool(reader.read_u8()? != 0)),
+        NUMBER_TAG => Ok(Value::number(reader.read_f64::<LittleEndian>()?)),
+        STRING_TAG => {
+            let length = read_length(reader)?;
+            let mut bytes = Vec::new();
+            reader.take(length as u64).read_to_end(&mut bytes)?;
+            if bytes.len() != length {
+                return Err(invalid_bytecode("Unexpected end of file"));
+            }
+            let string = String::from_utf8(bytes)
+                .map_err(|_| invalid_bytecode("String constant is not valid UTF-8"))?;
+            Ok(Value::string(string))
+        },
+        _ => Err(invalid_bytecode(&format!("Unknown constant type {}", tag))),
+    }
+}
+
+fn write_length<W: Write>(writer: &mut W, length: usize) -> InterpretResult<()> {
+    let length = u32::try_from(length)
+        .map_err(|_| InterpretError::CompileError("Chunk is too large to serialise".to_string()))?;
+    write_varint(writer, length)?;
+    Ok(())
+}
+
+fn read_length<R: Read>(reader: &mut R) -> InterpretResult<usize> {
+    match read_varint(reader) {
+        Ok(length) => Ok(length as usize),
+        Err(ref err) if err.kind() == io::ErrorKind::InvalidData => Err(invalid_bytecode("Length is malformed")),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn invalid_bytecode(message: &str) -> InterpretError {
+    InterpretError::InvalidBytecode(message.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+
+    fn serialise(chunk: &Chunk) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_chunk(&mut data, chunk).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chunk = compiler::compile_unoptimised("!(1.5 + 2 > 3)\n== (\"a\" + \"b\" == nil)").unwrap();
+
+        let data = serialise(&chunk);
+        assert!(is_bytecode(&data), "Expected data to have bytecode header");
+        let read_chunk = read_chunk(&mut &data[..]).unwrap();
+
+        assert_eq!(read_chunk.code, chunk.code);
+        assert_eq!(read_chunk.max_stack_depth, 3);
+        let constants: Vec<String> = read_chunk.constants.iter().map(|c| c.to_string()).collect();
+        assert_eq!(constants, vec!["Number(1.5)", "Number(2)", "Number(3)", "\"a\"", "\"b\""]);
+        let lines: Vec<&usize> = read_chunk.lines.into_iter().collect();
+        let expected_lines: Vec<&usize> = chunk.lines.into_iter().collect();
+        assert_eq!(lines, expected_lines);
+    }
+
+    #[test]
+    fn test_round_trip_nil_and_bool_constants() {
+        let mut chunk = Chunk::new();
+        chunk.constants.push(Value::nil());
+        chunk.constants.push(Value::bool(true));
+
+        let data = serialise(&chunk);
+        let read_chunk = read_chunk(&mut &data[..]).unwrap();
+
+        let constants: Vec<String> = read_chunk.constants.iter().map(|c| c.to_string()).collect();
+        assert_eq!(constants, vec!["Nil", "Bool(true)"]);
+    }
+
+    #[test]
+    fn test_round_trip_multi_byte_operands() {
+        let source: Vec<String> = (0..200).map(|i| i.to_string()).collect();
+        let chunk = compiler::compile_unoptimised(&source.join(" + ")).unwrap();
+
+        let data = serialise(&chunk);
+        let read_chunk = read_chunk(&mut &data[..]).unwrap();
+
+        assert_eq!(read_chunk.code, chunk.code);
+        assert_eq!(read_chunk.constants.len(), 200);
+        assert_eq!(read_chunk.constants[199].as_number(), 199.0);
+    }
+
+    #[test]
+    fn test_reject_incompatible_version() {
+        let chunk = compiler::compile("1").unwrap();
+        let mut data = serialise(&chunk);
+        data[4] = (FORMAT_VERSION + 1) as u8;
+
+        match read_chunk(&mut &data[..]) {
+            Err(InterpretError::InvalidBytecode(message)) => {
+                assert!(message.contains("version"), "Unexpected error message: {}", message);
+            },
+            _ => assert!(false, "Expected invalid bytecode error"),
+        }
+    }
+
+    #[test]
+    fn test_reject_bad_magic() {
+        let data = b"1 + 2".to_vec();
+        assert!(!is_bytecode(&data));
+        match read_chunk(&mut &data[..]) {
+            Err(InterpretError::InvalidBytecode(_)) => {},
+            _ => assert!(false, "Expected invalid bytecode error"),
+        }
+    }
+
+    #[test]
+    fn test_reject_empty_line_run() {
+        let chunk = compiler::compile("1").unwrap();
+        let mut data = serialise(&chunk);
+        // The last byte is the length of the last line run
+        *data.last_mut().unwrap() = 0;
+
+        match read_chunk(&mut &data[..]) {
+            Err(InterpretError::InvalidBytecode(message)) => assert_eq!(message, "Line run is empty"),
+            _ => assert!(false, "Expected invalid bytecode error"),
+        }
+    }
+
+    #[test]
+    fn test_reject_truncated_file() {
+        let chunk = compiler::compile("\"abc\" + \"def\"").unwrap();
+        let data = serialise(&chunk);
+        for length in 0..data.len() {
+            match read_chunk(&mut &data[..length]) {
+                Err(InterpretError::InvalidBytecode(_)) => {},
+                _ => assert!(false, "Expected invalid bytecode error when truncated to {} bytes", length),
+            }
+        }
+    }
+}
@@ -11,6 +11,12 @@ pub struct StringInterner {
     values: Vec<Box<str>>,
 }
 
+impl Default for StringInterner {
+    fn default() -> StringInterner {
+        StringInterner::new()
+    }
+}
+
 impl StringInterner {
     pub fn new() -> StringInterner {
         StringInterner {
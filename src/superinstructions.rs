@@ -0,0 +1,103 @@
+use ::chunk::Chunk;
+use ::errors::InterpretResult;
+use ::instructions::{Instruction, OpCode};
+
+/// Rewrite a chunk to replace a `Constant` load followed by an
+/// instruction with a superinstruction form, so that both are
+/// executed with a single dispatch.
+pub fn fuse(chunk: &Chunk) -> InterpretResult<Chunk> {
+    let mut result = Chunk::new();
+    result.constants = chunk.constants.clone();
+
+    let mut pending: Option<(u32, usize)> = None;
+    for decoded in chunk.instructions() {
+        let (_, instruction, line) = decoded?;
+        let line = line.unwrap_or(0);
+        if let Some((index, load_line)) = pending.take() {
+            match fused(instruction, index) {
+                // Use the line of the operation, as only it can raise a runtime error
+                Some(fused) => {
+                    result.write_instruction(fused, line);
+                    continue;
+                },
+                None => result.write_instruction(Instruction::Constant(index), load_line),
+            }
+        }
+        match instruction {
+            Instruction::Constant(index) => pending = Some((index, line)),
+            _ => result.write_instruction(instruction, line),
+        }
+    }
+    if let Some((index, load_line)) = pending {
+        result.write_instruction(Instruction::Constant(index), load_line);
+    }
+    Ok(result)
+}
+
+/// The superinstruction for an instruction that follows a constant load
+fn fused(instruction: Instruction, constant_index: u32) -> Option<Instruction> {
+    let op_code = match instruction {
+        Instruction::Add => OpCode::AddConstant,
+        Instruction::Subtract => OpCode::SubtractConstant,
+        Instruction::Less => OpCode::LessConstant,
+        Instruction::Greater => OpCode::GreaterConstant,
+        _ => return None,
+    };
+    Instruction::with_constant(op_code, constant_index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+    use ::errors::InterpretError;
+    use ::virtual_machine::VirtualMachine;
+
+    #[test]
+    fn test_fuse_constant_operands() {
+        let chunk = compiler::compile_unoptimised("1 + 2 - 3 < 4 == 5 > 6").unwrap();
+        let chunk = fuse(&chunk).unwrap();
+
+        assert_eq!(chunk.decoded_instructions(), vec![
+            Instruction::Constant(0),
+            Instruction::AddConstant(1),
+            Instruction::SubtractConstant(2),
+            Instruction::LessConstant(3),
+            Instruction::Constant(4),
+            Instruction::GreaterConstant(5),
+            Instruction::Equal,
+            Instruction::Return,
+        ]);
+        assert!(!VirtualMachine::new().interpret(&chunk).unwrap().as_bool());
+    }
+
+    #[test]
+    fn test_fused_instruction_has_operation_line() {
+        let chunk = compiler::compile_unoptimised("true\n-\n\"a\"").unwrap();
+        let chunk = fuse(&chunk).unwrap();
+
+        assert_eq!(chunk.decoded_instructions(), vec![
+            Instruction::True,
+            Instruction::SubtractConstant(0),
+            Instruction::Return,
+        ]);
+        let lines: Vec<Option<usize>> = chunk.instructions().map(|result| result.unwrap().2).collect();
+        assert_eq!(lines, vec![Some(1), Some(3), Some(3)]);
+        match VirtualMachine::new().interpret(&chunk) {
+            Err(InterpretError::RuntimeError(details)) => {
+                assert_eq!(details.message, "Operands must be numbers");
+                assert_eq!(details.stack_trace[0].line, 3);
+            },
+            result => assert!(false, "Expected runtime error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_add_constant_concatenates_strings() {
+        let chunk = compiler::compile_unoptimised("\"a\" + \"b\"").unwrap();
+        let chunk = fuse(&chunk).unwrap();
+
+        assert_eq!(chunk.decoded_instructions()[1], Instruction::AddConstant(1));
+        assert_eq!(VirtualMachine::new().interpret(&chunk).unwrap().as_string(), "ab");
+    }
+}
@@ -0,0 +1,224 @@
+use std::io;
+use std::io::Write;
+
+use ::chunk::Chunk;
+use ::debug;
+use ::instructions::*;
+use ::value::Value;
+
+#[derive(Debug,Copy,Clone,PartialEq)]
+pub enum TraceFormat {
+    /// Disassembled instructions followed by the stack
+    Text,
+    /// One JSON object per instruction
+    JsonLines,
+}
+
+/// Controls which executed instructions are traced and how
+#[derive(Debug,Clone)]
+pub struct TraceOptions {
+    pub format: TraceFormat,
+    /// Only trace instructions from this inclusive range of lines
+    pub lines: Option<(usize, usize)>,
+    /// Only trace these op codes
+    pub op_codes: Option<Vec<OpCode>>,
+}
+
+impl TraceOptions {
+    pub fn new(format: TraceFormat) -> TraceOptions {
+        TraceOptions {
+            format,
+            lines: None,
+            op_codes: None,
+        }
+    }
+
+    fn includes(&self, line: usize, op_code: OpCode) -> bool {
+        let line_included = match self.lines {
+            Some((start, end)) => line >= start && line <= end,
+            None => true,
+        };
+        let op_code_included = match &self.op_codes {
+            Some(op_codes) => op_codes.contains(&op_code),
+            None => true,
+        };
+        line_included && op_code_included
+    }
+}
+
+/// Writes a trace of the instructions executed by a virtual machine
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    options: TraceOptions,
+}
+
+impl Tracer {
+    pub fn new(writer: Box<dyn Write>, options: TraceOptions) -> Tracer {
+        Tracer { writer, options }
+    }
+
+    pub fn stdout(options: TraceOptions) -> Tracer {
+        Tracer::new(Box::new(io::stdout()), options)
+    }
+
+    /// Trace the instruction at the given offset, after it has been executed
+    pub fn trace_instruction(&mut self, chunk: &Chunk, offset: usize, stack: &[Value]) -> io::Result<()> {
+        let instruction = match Instruction::decode(&chunk.code, offset) {
+            Ok((instruction, _)) => instruction,
+            Err(_) => return Ok(()),
+        };
+        let op_code = instruction.op_code();
+        let line = chunk.lines.get(offset).cloned().unwrap_or(0);
+        if !self.options.includes(line, op_code) {
+            return Ok(());
+        }
+        match self.options.format {
+            TraceFormat::Text => {
+                debug::disassemble_instruction(&mut self.writer, chunk, offset)?;
+                let stack: Vec<String> = stack.iter().map(|value| value.to_string()).collect();
+                writeln!(self.writer, "          [{}]", stack.join(", "))
+            },
+            TraceFormat::JsonLines => {
+                let operands: Vec<String> = instruction.constant_index()
+                    .iter().map(|operand| operand.to_string()).collect();
+                let stack: Vec<String> = stack.iter()
+                    .map(|value| json_string(&value.to_string())).collect();
+                writeln!(
+                    self.writer,
+                    "{{\"offset\":{},\"line\":{},\"opcode\":\"{:?}\",\"operands\":[{}],\"stack\":[{}]}}",
+                    offset, line, op_code, operands.join(","), stack.join(","))
+            },
+        }
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Parse a line range of the form "start-end", or a single line number
+pub fn parse_line_range(range: &str) -> Option<(usize, usize)> {
+    let mut parts = range.splitn(2, '-');
+    let start = parts.next()?.trim().parse().ok()?;
+    let end = match parts.next() {
+        Some(end) => end.trim().parse().ok()?,
+        None => start,
+    };
+    if start <= end { Some((start, end)) } else { None }
+}
+
+/// Parse a comma separated list of op code names
+pub fn parse_op_codes(op_codes: &str) -> Option<Vec<OpCode>> {
+    op_codes.split(',')
+        .map(|name| OpCode::from_name(name.trim()))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use super::*;
+
+    /// Writer that can be inspected after being handed to a tracer
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(chunk: &Chunk, options: TraceOptions) -> String {
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), options);
+        let stack = vec![Value::number(1.0), Value::bool(true)];
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            tracer.trace_instruction(chunk, offset, &stack).unwrap();
+            offset += Instruction::decode(&chunk.code, offset).unwrap().1;
+        }
+        let output = buffer.0.borrow().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn test_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::number(1.0), 1).unwrap();
+        chunk.write_instruction(SimpleInstruction::new(OpCode::True), 2);
+        chunk.write_instruction(SimpleInstruction::new(OpCode::Return), 3);
+        chunk
+    }
+
+    #[test]
+    fn test_text_trace() {
+        let output = trace(&test_chunk(), TraceOptions::new(TraceFormat::Text));
+        assert_eq!(output, "\
+0000    1 OpCode::Constant    0 'Number(1)'
+          [Number(1), Bool(true)]
+0002    2 OpCode::True
+          [Number(1), Bool(true)]
+0003    3 OpCode::Return
+          [Number(1), Bool(true)]
+");
+    }
+
+    #[test]
+    fn test_json_lines_trace() {
+        let mut options = TraceOptions::new(TraceFormat::JsonLines);
+        options.op_codes = Some(vec![OpCode::Constant]);
+        let output = trace(&test_chunk(), options);
+        assert_eq!(
+            output,
+            "{\"offset\":0,\"line\":1,\"opcode\":\"Constant\",\"operands\":[0],\"stack\":[\"Number(1)\",\"Bool(true)\"]}\n");
+    }
+
+    #[test]
+    fn test_trace_line_filter() {
+        let mut options = TraceOptions::new(TraceFormat::JsonLines);
+        options.lines = Some((2, 3));
+        let output = trace(&test_chunk(), options);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("\"opcode\":\"True\""), "Unexpected trace: {}", lines[0]);
+        assert!(lines[1].contains("\"opcode\":\"Return\""), "Unexpected trace: {}", lines[1]);
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("\"a\"\\\n"), "\"\\\"a\\\"\\\\\\n\"");
+    }
+
+    #[test]
+    fn test_parse_line_range() {
+        assert_eq!(parse_line_range("3-7"), Some((3, 7)));
+        assert_eq!(parse_line_range("4"), Some((4, 4)));
+        assert_eq!(parse_line_range("7-3"), None);
+        assert_eq!(parse_line_range("a-3"), None);
+    }
+
+    #[test]
+    fn test_parse_op_codes() {
+        assert_eq!(parse_op_codes("Add, Constant"), Some(vec![OpCode::Add, OpCode::Constant]));
+        assert_eq!(parse_op_codes("Add,Bogus"), None);
+    }
+}
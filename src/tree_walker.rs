@@ -0,0 +1,160 @@
+use ::ast::{BinaryOperator, Expr, UnaryOperator};
+use ::errors::{InterpretError, InterpretResult, RuntimeErrorDetails, StackTraceFrame};
+use ::object::LoxObject;
+use ::value::Value;
+
+/// Evaluate an expression by walking its tree. This is a reference
+/// implementation of Lox semantics that is kept as simple as possible,
+/// and doesn't share any code with the virtual machines beyond `Value`,
+/// so that it can be used to check their results.
+/// Runtime errors are reported on the line of the operator.
+pub fn evaluate(expr: &Expr) -> InterpretResult<Value> {
+    match expr {
+        Expr::Nil => Ok(Value::nil()),
+        Expr::Bool(value) => Ok(Value::bool(*value)),
+        Expr::Number(value) => Ok(Value::number(*value)),
+        Expr::String(value) => Ok(Value::string(value.clone())),
+        Expr::Unary { operator, operand, line } => {
+            let operand = evaluate(operand)?;
+            match operator {
+                UnaryOperator::Not => Ok(Value::bool(!is_truthy(&operand))),
+                UnaryOperator::Negate => {
+                    if !operand.is_number() {
+                        return runtime_error("Operand must be a number", *line);
+                    }
+                    Ok(Value::number(-operand.as_number()))
+                },
+            }
+        },
+        Expr::Binary { operator, left, right, line } => {
+            let left = evaluate(left)?;
+            let right = evaluate(right)?;
+            binary(*operator, &left, &right, *line)
+        },
+    }
+}
+
+fn binary(operator: BinaryOperator, left: &Value, right: &Value, line: usize) -> InterpretResult<Value> {
+    match operator {
+        BinaryOperator::Equal => return Ok(Value::bool(equal(left, right))),
+        BinaryOperator::NotEqual => return Ok(Value::bool(!equal(left, right))),
+        BinaryOperator::Add if left.is_string() && right.is_string() =>
+            return Ok(Value::string(left.as_string() + &right.as_string())),
+        _ => {},
+    }
+
+    if !(left.is_number() && right.is_number()) {
+        let message = match operator {
+            BinaryOperator::Add => "Operands must be two numbers or two strings",
+            _ => "Operands must be numbers",
+        };
+        return runtime_error(message, line);
+    }
+    let (a, b) = (left.as_number(), right.as_number());
+    Ok(match operator {
+        BinaryOperator::Add => Value::number(a + b),
+        BinaryOperator::Subtract => Value::number(a - b),
+        BinaryOperator::Multiply => Value::number(a * b),
+        BinaryOperator::Divide => Value::number(a / b),
+        BinaryOperator::Greater => Value::bool(a > b),
+        BinaryOperator::GreaterEqual => Value::bool(a >= b),
+        BinaryOperator::Less => Value::bool(a < b),
+        BinaryOperator::LessEqual => Value::bool(a <= b),
+        BinaryOperator::Equal | BinaryOperator::NotEqual => unreachable!(),
+    })
+}
+
+/// Only nil and false are falsey
+fn is_truthy(value: &Value) -> bool {
+    !(value.is_nil() || (value.is_bool() && !value.as_bool()))
+}
+
+/// Values of different types are never equal, and
+/// numbers are compared with IEEE semantics
+fn equal(left: &Value, right: &Value) -> bool {
+    if left.is_nil() || right.is_nil() {
+        left.is_nil() && right.is_nil()
+    } else if left.is_bool() && right.is_bool() {
+        left.as_bool() == right.as_bool()
+    } else if left.is_number() && right.is_number() {
+        left.as_number() == right.as_number()
+    } else if left.is_object() && right.is_object() {
+        match (left.as_object(), right.as_object()) {
+            (LoxObject::String(left), LoxObject::String(right)) => left == right,
+        }
+    } else {
+        false
+    }
+}
+
+fn runtime_error<T>(message: &str, line: usize) -> InterpretResult<T> {
+    Err(InterpretError::RuntimeError(RuntimeErrorDetails {
+        message: message.to_string(),
+        stack_trace: vec![StackTraceFrame {
+            function_name: "script".to_string(),
+            source_file: None,
+            line,
+            column: None,
+        }],
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::parser;
+
+    fn run(source: &str) -> InterpretResult<Value> {
+        evaluate(&parser::parse(source).unwrap())
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(run("1 + 2 * 3 - 4 / 8").unwrap().as_number(), 6.5);
+        assert_eq!(run("-(2 - 5)").unwrap().as_number(), 3.0);
+        assert!(run("0 / 0").unwrap().as_number().is_nan());
+    }
+
+    #[test]
+    fn test_comparison_and_equality() {
+        assert!(run("1 <= 1 == !(2 > 3)").unwrap().as_bool());
+        assert!(!run("0 / 0 >= 1").unwrap().as_bool());
+        assert!(run("(0 / 0) != (0 / 0)").unwrap().as_bool());
+        assert!(!run("nil == false").unwrap().as_bool());
+        assert!(run("\"ab\" == \"a\" + \"b\"").unwrap().as_bool());
+        assert!(!run("1 == \"1\"").unwrap().as_bool());
+    }
+
+    #[test]
+    fn test_truthiness() {
+        assert!(run("!nil").unwrap().as_bool());
+        assert!(!run("!0").unwrap().as_bool());
+        assert!(!run("!\"\"").unwrap().as_bool());
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let cases = vec![
+            ("-\"a\"", "Operand must be a number", 1),
+            ("1 +\n\"a\"", "Operands must be two numbers or two strings", 1),
+            ("true\n\n< 1", "Operands must be numbers", 3),
+        ];
+        for (source, expected_message, expected_line) in cases {
+            match run(source) {
+                Err(InterpretError::RuntimeError(details)) => {
+                    assert_eq!(details.message, expected_message);
+                    assert_eq!(details.stack_trace[0].line, expected_line);
+                },
+                result => assert!(false, "Expected runtime error running '{}', got {:?}", source, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_left_operand_error_is_reported_first() {
+        match run("-nil + (1 < \"a\")") {
+            Err(InterpretError::RuntimeError(details)) => assert_eq!(details.message, "Operand must be a number"),
+            result => assert!(false, "Expected runtime error, got {:?}", result),
+        }
+    }
+}
@@ -1,7 +1,16 @@
 use std::fmt;
+#[cfg(not(feature="nan-boxing"))]
 use std::rc::Rc;
+#[cfg(not(feature="nan-boxing"))]
 use ::object::{LoxObject};
 
+#[cfg(feature="nan-boxing")]
+pub use ::nan_boxing::Value;
+
+/// A value tagged with its type. With the nan-boxing feature this is
+/// replaced with an 8 byte NaN-boxed value that has the same methods,
+/// so code outside this module shouldn't match on the variants.
+#[cfg(not(feature="nan-boxing"))]
 #[derive(Debug,Clone)]
 pub enum Value {
     NilValue,
@@ -10,6 +19,7 @@ pub enum Value {
     ObjValue(Rc<LoxObject>)
 }
 
+#[cfg(not(feature="nan-boxing"))]
 impl Value {
     pub fn nil() -> Value {
         Value::NilValue
@@ -23,6 +33,29 @@ impl Value {
         Value::NumberValue(val)
     }
 
+    pub fn object(obj: Rc<LoxObject>) -> Value {
+        Value::ObjValue(obj)
+    }
+
+    pub fn string(val: String) -> Value {
+        Value::object(Rc::new(LoxObject::String(val)))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::NilValue)
+    }
+
+    pub fn is_bool(&self) -> bool {
+        matches!(self, Value::BoolValue(_))
+    }
+
+    pub fn as_bool(&self) -> bool {
+        match &self {
+            &Value::BoolValue(val) => *val,
+            _ => panic!("Value is not a BoolValue"),
+        }
+    }
+
     pub fn as_number(&self) -> f64 {
         match &self {
             &Value::NumberValue(val) => val.clone(),
@@ -37,6 +70,17 @@ impl Value {
         }
     }
 
+    pub fn is_object(&self) -> bool {
+        matches!(self, Value::ObjValue(_))
+    }
+
+    pub fn as_object(&self) -> &LoxObject {
+        match &self {
+            &Value::ObjValue(obj) => obj,
+            _ => panic!("Value is not an ObjValue"),
+        }
+    }
+
     pub fn is_string(&self) -> bool {
         match self {
             Value::ObjValue(obj) => {
@@ -64,11 +108,59 @@ impl Value {
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &self {
-            &Value::NilValue => write!(f, "Nil"),
-            &Value::BoolValue(val) => write!(f, "Bool({})", val),
-            &Value::NumberValue(val) => write!(f, "Number({})", val),
-            &Value::ObjValue(val) => (**val).fmt(f),
+        if self.is_nil() {
+            write!(f, "Nil")
+        } else if self.is_bool() {
+            write!(f, "Bool({})", self.as_bool())
+        } else if self.is_number() {
+            write!(f, "Number({})", self.as_number())
+        } else {
+            self.as_object().fmt(f)
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_predicates() {
+        let values = vec![Value::nil(), Value::bool(false), Value::number(1.5), Value::string("a".to_string())];
+        let predicates: Vec<(bool, bool, bool, bool)> = values.iter()
+            .map(|value| (value.is_nil(), value.is_bool(), value.is_number(), value.is_string()))
+            .collect();
+
+        assert_eq!(predicates, vec![
+            (true, false, false, false),
+            (false, true, false, false),
+            (false, false, true, false),
+            (false, false, false, true),
+        ]);
+        assert!(values[3].is_object());
+    }
+
+    #[test]
+    fn test_accessors() {
+        assert!(Value::bool(true).as_bool());
+        assert_eq!(Value::number(-0.25).as_number(), -0.25);
+        assert!(Value::number(f64::NAN).as_number().is_nan());
+        assert_eq!(Value::string("abc".to_string()).as_string(), "abc");
+    }
+
+    #[test]
+    fn test_display() {
+        let values: Vec<String> = vec![
+            Value::nil(), Value::bool(true), Value::number(2.0), Value::string("s".to_string()),
+        ].iter().map(|value| value.to_string()).collect();
+        assert_eq!(values, vec!["Nil", "Bool(true)", "Number(2)", "\"s\""]);
+    }
+
+    #[test]
+    fn test_clone_string() {
+        let value = Value::string("abc".to_string());
+        let cloned = value.clone();
+        drop(value);
+        assert_eq!(cloned.as_string(), "abc");
+    }
+}
@@ -0,0 +1,211 @@
+use std::fmt;
+
+use ::chunk::Chunk;
+use ::errors::InterpretError;
+use ::instructions::*;
+
+/// A problem found in a chunk that would stop it executing correctly
+#[derive(Debug,Clone,PartialEq)]
+pub struct Violation {
+    /// Offset of the instruction or byte with the problem
+    pub offset: usize,
+    pub kind: ViolationKind,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ViolationKind {
+    UnknownOpCode(u8),
+    TruncatedInstruction(OpCode),
+    /// A varint operand is too large to decode or isn't in its shortest form
+    MalformedOperand(OpCode),
+    BadConstantIndex(usize),
+    /// Bytes from this offset have no line number
+    MissingLines,
+    /// The line table has entries past the end of the code
+    ExtraLines,
+    /// The instruction pops more values than are on the stack
+    StackUnderflow(OpCode),
+    /// The instruction pushes more values than the chunk's declared maximum stack depth
+    StackDepthExceeded { declared: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}: ", self.offset)?;
+        match &self.kind {
+            ViolationKind::UnknownOpCode(byte) => write!(f, "Unknown op code {}", byte),
+            ViolationKind::TruncatedInstruction(op_code) => write!(f, "Truncated {:?} instruction", op_code),
+            ViolationKind::MalformedOperand(op_code) => write!(f, "Malformed {:?} operand", op_code),
+            ViolationKind::BadConstantIndex(index) => write!(f, "Constant index {} is out of range", index),
+            ViolationKind::MissingLines => write!(f, "No line number information"),
+            ViolationKind::ExtraLines => write!(f, "Line number information past the end of the code"),
+            ViolationKind::StackUnderflow(op_code) => write!(f, "{:?} would underflow the stack", op_code),
+            ViolationKind::StackDepthExceeded { declared } =>
+                write!(f, "Stack depth exceeds the declared maximum of {}", declared),
+        }
+    }
+}
+
+/// Check a chunk is well formed before it is executed,
+/// returning all violations found in order of offset
+pub fn verify(chunk: &Chunk) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut stack_depth = 0;
+    let mut exceeded_max_depth = false;
+
+    for result in chunk.instructions() {
+        let (offset, instruction) = match result {
+            Ok((offset, instruction, _)) => (offset, instruction),
+            Err(InterpretError::UnknownOpCode { offset, byte }) => {
+                violations.push(Violation { offset, kind: ViolationKind::UnknownOpCode(byte) });
+                continue;
+            },
+            Err(InterpretError::TruncatedInstruction { offset }) => {
+                let op_code = OpCode::from_byte(chunk.code[offset]).unwrap();
+                violations.push(Violation { offset, kind: ViolationKind::TruncatedInstruction(op_code) });
+                continue;
+            },
+            Err(InterpretError::MalformedOperand { offset }) => {
+                let op_code = OpCode::from_byte(chunk.code[offset]).unwrap();
+                violations.push(Violation { offset, kind: ViolationKind::MalformedOperand(op_code) });
+                continue;
+            },
+            Err(err) => unreachable!("Unexpected error decoding instructions: {}", err),
+        };
+
+        match instruction.constant_index() {
+            Some(index) if index >= chunk.constants.len() => {
+                violations.push(Violation { offset, kind: ViolationKind::BadConstantIndex(index) });
+            },
+            _ => {},
+        }
+
+        let op_code = instruction.op_code();
+        let (pops, pushes) = op_code.stack_effect();
+        if pops > stack_depth {
+            violations.push(Violation { offset, kind: ViolationKind::StackUnderflow(op_code) });
+            stack_depth = 0;
+        } else {
+            stack_depth -= pops;
+        }
+        stack_depth += pushes;
+        if stack_depth > chunk.max_stack_depth && !exceeded_max_depth {
+            let declared = chunk.max_stack_depth;
+            violations.push(Violation { offset, kind: ViolationKind::StackDepthExceeded { declared } });
+            exceeded_max_depth = true;
+        }
+    }
+
+    let line_count = chunk.lines.len();
+    if line_count < chunk.code.len() {
+        violations.push(Violation { offset: line_count, kind: ViolationKind::MissingLines });
+    } else if line_count > chunk.code.len() {
+        violations.push(Violation { offset: chunk.code.len(), kind: ViolationKind::ExtraLines });
+    }
+
+    violations.sort_by_key(|violation| violation.offset);
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+    use ::value::Value;
+
+    fn chunk_with_code(code: Vec<u8>, constants: Vec<Value>) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.lines.push_run(1, code.len());
+        chunk.code = code;
+        chunk.constants = constants;
+        chunk.max_stack_depth = chunk.compute_max_stack_depth();
+        chunk
+    }
+
+    #[test]
+    fn test_compiled_chunk_is_valid() {
+        let chunk = compiler::compile_unoptimised("!(1 + 2 > 3) == (\"a\" + \"b\" == nil)").unwrap();
+        assert_eq!(verify(&chunk), vec![]);
+    }
+
+    #[test]
+    fn test_unknown_op_code() {
+        let chunk = chunk_with_code(vec![OpCode::Nil.as_byte(), 255, OpCode::Return.as_byte()], vec![]);
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 1, kind: ViolationKind::UnknownOpCode(255) },
+        ]);
+    }
+
+    #[test]
+    fn test_bad_constant_index() {
+        let chunk = chunk_with_code(vec![
+            OpCode::Constant.as_byte(), 0,
+            OpCode::AddConstant.as_byte(), 0x80, 0x01,
+            OpCode::Return.as_byte(),
+        ], vec![Value::nil()]);
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 2, kind: ViolationKind::BadConstantIndex(128) },
+        ]);
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        let chunk = chunk_with_code(vec![OpCode::Nil.as_byte(), OpCode::Constant.as_byte(), 0x80, 0x80], vec![]);
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 1, kind: ViolationKind::TruncatedInstruction(OpCode::Constant) },
+        ]);
+    }
+
+    #[test]
+    fn test_malformed_operand() {
+        let chunk = chunk_with_code(vec![
+            OpCode::Constant.as_byte(), 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+            OpCode::Return.as_byte(),
+        ], vec![]);
+        assert_eq!(verify(&chunk)[0],
+            Violation { offset: 0, kind: ViolationKind::MalformedOperand(OpCode::Constant) });
+    }
+
+    #[test]
+    fn test_missing_lines() {
+        let mut chunk = chunk_with_code(vec![OpCode::Nil.as_byte(), OpCode::Return.as_byte()], vec![]);
+        chunk.lines = ::run_length_encoding::RunLengthEncoded::new();
+        chunk.lines.push(1);
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 1, kind: ViolationKind::MissingLines },
+        ]);
+    }
+
+    #[test]
+    fn test_extra_lines() {
+        let mut chunk = chunk_with_code(vec![OpCode::Nil.as_byte(), OpCode::Return.as_byte()], vec![]);
+        chunk.lines.push(2);
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 2, kind: ViolationKind::ExtraLines },
+        ]);
+    }
+
+    #[test]
+    fn test_stack_depth_exceeded() {
+        let mut chunk = compiler::compile_unoptimised("1 + (2 + (3 + 4)) + 5").unwrap();
+        assert_eq!(chunk.max_stack_depth, 4);
+        chunk.max_stack_depth = 2;
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 4, kind: ViolationKind::StackDepthExceeded { declared: 2 } },
+        ]);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let chunk = chunk_with_code(vec![
+            OpCode::Nil.as_byte(),
+            OpCode::Add.as_byte(),
+            OpCode::Return.as_byte(),
+            OpCode::Return.as_byte(),
+        ], vec![]);
+        assert_eq!(verify(&chunk), vec![
+            Violation { offset: 1, kind: ViolationKind::StackUnderflow(OpCode::Add) },
+            Violation { offset: 3, kind: ViolationKind::StackUnderflow(OpCode::Return) },
+        ]);
+    }
+}
@@ -1,115 +1,233 @@
-use std::io::{Cursor, Read};
-use std::rc::Rc;
+use fnv::FnvHashMap;
 
 use ::chunk::Chunk;
-use ::errors::{InterpretError, InterpretResult};
-use ::instructions;
-use ::instructions::InstructionRead;
-use ::instructions::OpCode;
+use ::errors::{InterpretError, InterpretResult, RuntimeErrorDetails, StackTraceFrame};
+use ::instructions::{self, OpCode};
 use ::object;
-use ::object::{LoxObject};
+use ::trace::Tracer;
 use ::value::Value;
 
-pub struct VirtualMachine<'a> {
-    chunk: &'a Chunk,
-    cursor: Cursor<&'a Vec<u8>>,
+/// The default limit on the stack depth of chunks that can be run
+pub const DEFAULT_STACK_LIMIT: usize = 1 << 16;
+
+/// A virtual machine that can interpret multiple chunks in turn,
+/// keeping its state between them so a REPL session can be run
+/// with a single VM.
+pub struct VirtualMachine {
     stack: Vec<Value>,
+    /// Offset of the instruction currently being executed
+    instruction_offset: usize,
+    source_file: Option<String>,
+    tracer: Option<Tracer>,
+    op_codes: [Option<OpCode>; 256],
+    stack_limit: usize,
+    /// Global variables, which persist between chunks
+    globals: FnvHashMap<String, Value>,
+}
+
+impl Default for VirtualMachine {
+    fn default() -> VirtualMachine {
+        VirtualMachine::new()
+    }
 }
 
-impl<'a> VirtualMachine<'a> {
-    pub fn new(chunk: &Chunk) -> VirtualMachine {
+impl VirtualMachine {
+    pub fn new() -> VirtualMachine {
         VirtualMachine {
-            chunk,
-            cursor: Cursor::new(&chunk.code),
-            stack: Vec::with_capacity(256),
+            stack: Vec::new(),
+            instruction_offset: 0,
+            source_file: None,
+            tracer: None,
+            op_codes: OpCode::table(),
+            stack_limit: DEFAULT_STACK_LIMIT,
+            globals: FnvHashMap::default(),
         }
     }
 
-    pub fn interpret(&mut self) -> InterpretResult<()> {
+    /// Set the name of the source file the chunk was compiled from,
+    /// for use in runtime error stack traces
+    pub fn set_source_file(&mut self, source_file: Option<&str>) {
+        self.source_file = source_file.map(|s| s.to_string());
+    }
+
+    /// Enable or disable tracing of executed instructions
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /// Set the maximum stack depth of chunks that can be run
+    pub fn set_stack_limit(&mut self, stack_limit: usize) {
+        self.stack_limit = stack_limit;
+    }
+
+    /// Define or redefine a global variable. The language can't declare
+    /// globals yet, so this is how an embedder registers native values.
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    /// The defined global variables, sorted by name
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let mut globals: Vec<(&str, &Value)> = self.globals.iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        globals.sort_by_key(|&(name, _)| name);
+        globals
+    }
+
+    /// Run a chunk, returning the value left by its return instruction.
+    /// Chunks that declare a stack depth over the limit are rejected
+    /// before running, otherwise the stack is allocated up front.
+    pub fn interpret(&mut self, chunk: &Chunk) -> InterpretResult<Value> {
+        if chunk.max_stack_depth > self.stack_limit {
+            return Err(InterpretError::StackLimitExceeded {
+                depth: chunk.max_stack_depth,
+                limit: self.stack_limit,
+            });
+        }
+        self.stack.reserve_exact(chunk.max_stack_depth);
+        let result = self.run(chunk);
+        // Don't leave values from a failed run behind for the next chunk
+        self.reset_stack();
+        result
+    }
+
+    /// Execute a chunk, indexing directly into its code rather than
+    /// decoding each instruction with `Instruction::decode`.
+    /// Malformed code is still reported as an error, but only with
+    /// cheap bounds checks, and full checks are left to the verifier.
+    /// Errors are only built when a check fails, as building and
+    /// dropping one for every instruction dominates the run time.
+    fn run(&mut self, chunk: &Chunk) -> InterpretResult<Value> {
+        let code = &chunk.code[..];
+        let mut ip = 0;
         loop {
-            #[cfg(feature="debug-trace-execution")]
-            {
-                println!("Stack: {:?}", &self.stack);
-                ::debug::disassemble_instruction(&self.chunk, self.cursor.position() as usize);
-                println!("");
-            }
-            let instruction = self.read_byte();
-            match instruction {
-                Some(OpCode::Add) => {
-                    if self.peek(0).is_string() && self.peek(1).is_string() {
-                        let b = self.pop();
-                        let a = self.pop();
-                        self.push(Value::ObjValue(Rc::new(LoxObject::String(format!("{}{}", a.as_string(), b.as_string())))));
-                    }
-                    else if self.peek(0).is_number() && self.peek(1).is_number() {
-                        let b = self.pop();
-                        let a = self.pop();
-                        self.push(Value::number(a.as_number() + b.as_number()));
-                    }
-                    else {
-                        return self.runtime_error("Operands must be two numbers or two strings");
-                    }
-                },
-                Some(OpCode::Constant) => {
-                    let value = self.read_constant();
-                    self.push(value);
+            self.instruction_offset = ip;
+            let byte = match code.get(ip) {
+                Some(byte) => *byte,
+                None => return Err(InterpretError::TruncatedInstruction { offset: ip }),
+            };
+            let op_code = match self.op_codes[byte as usize] {
+                Some(op_code) => op_code,
+                None => return Err(InterpretError::UnknownOpCode { offset: ip, byte }),
+            };
+            ip += 1;
+            match op_code {
+                OpCode::Add => {
+                    self.add(chunk)?;
                 },
-                Some(OpCode::ConstantLong) => {
-                    let value = self.read_constant_long();
+                OpCode::Constant => {
+                    let index = self.read_varint(code, &mut ip)?;
+                    let value = self.constant(chunk, index)?;
                     self.push(value);
                 },
-                Some(OpCode::True) => {
+                OpCode::True => {
                     self.push(Value::bool(true));
                 },
-                Some(OpCode::False) => {
+                OpCode::False => {
                     self.push(Value::bool(false));
                 },
-                Some(OpCode::Nil) => {
+                OpCode::Nil => {
                     self.push(Value::nil());
                 },
-                Some(OpCode::Divide) => {
-                    self.binary_op(|a, b| {a / b}, Value::number)?;
-                },
-                Some(OpCode::Multiply) => {
-                    self.binary_op(|a, b| {a * b}, Value::number)?;
-                },
-                Some(OpCode::Negate) => {
-                    let value = self.pop();
-                    match value {
-                        Value::NumberValue(value) => {
-                            self.push(Value::number(-value));
-                        },
-                        _ => {
-                            return self.runtime_error("Operand must be a number");
-                        }
+                OpCode::Divide => {
+                    self.binary_op(chunk, |a, b| {a / b}, Value::number)?;
+                },
+                OpCode::Multiply => {
+                    self.binary_op(chunk, |a, b| {a * b}, Value::number)?;
+                },
+                OpCode::Negate => {
+                    let value = self.pop()?;
+                    if !value.is_number() {
+                        return self.runtime_error(chunk, "Operand must be a number");
                     }
+                    self.push(Value::number(-value.as_number()));
                 },
-                Some(OpCode::Return) => {
-                    return Ok(());
+                OpCode::Return => {
+                    let value = self.pop()?;
+                    self.trace_instruction(chunk)?;
+                    return Ok(value);
                 },
-                Some(OpCode::Subtract) => {
-                    self.binary_op(|a, b| {a - b}, Value::number)?;
+                OpCode::Subtract => {
+                    self.binary_op(chunk, |a, b| {a - b}, Value::number)?;
                 },
-                Some(OpCode::Not) => {
-                    let value = is_falsey(self.pop());
+                OpCode::Not => {
+                    let value = is_falsey(self.pop()?);
                     self.push(Value::bool(value));
                 },
-                Some(OpCode::Equal) => {
-                    let left = self.pop();
-                    let right = self.pop();
+                OpCode::Equal => {
+                    let left = self.pop()?;
+                    let right = self.pop()?;
                     self.push(Value::bool(values_equal(left, right)));
                 },
-                Some(OpCode::Greater) => {
-                    self.binary_op(|a, b| a > b, Value::bool)?;
+                OpCode::Greater => {
+                    self.binary_op(chunk, |a, b| a > b, Value::bool)?;
+                },
+                OpCode::Less => {
+                    self.binary_op(chunk, |a, b| a < b, Value::bool)?;
+                },
+                OpCode::NotEqual => {
+                    let left = self.pop()?;
+                    let right = self.pop()?;
+                    self.push(Value::bool(!values_equal(left, right)));
+                },
+                OpCode::GreaterEqual => {
+                    self.binary_op(chunk, |a, b| a >= b, Value::bool)?;
+                },
+                OpCode::LessEqual => {
+                    self.binary_op(chunk, |a, b| a <= b, Value::bool)?;
+                },
+                OpCode::AddConstant => {
+                    let index = self.read_varint(code, &mut ip)?;
+                    let value = self.constant(chunk, index)?;
+                    self.add_operand(chunk, value)?;
                 },
-                Some(OpCode::Less) => {
-                    self.binary_op(|a, b| a < b, Value::bool)?;
+                OpCode::SubtractConstant => {
+                    let index = self.read_varint(code, &mut ip)?;
+                    let value = self.constant(chunk, index)?;
+                    self.binary_op_operand(chunk, value, |a, b| {a - b}, Value::number)?;
                 },
-                None => {
-                    let message = format!("Unrecognised op code: {:?}", instruction);
-                    return Err(InterpretError::CompileError(message));
+                OpCode::LessConstant => {
+                    let index = self.read_varint(code, &mut ip)?;
+                    let value = self.constant(chunk, index)?;
+                    self.binary_op_operand(chunk, value, |a, b| a < b, Value::bool)?;
+                },
+                OpCode::GreaterConstant => {
+                    let index = self.read_varint(code, &mut ip)?;
+                    let value = self.constant(chunk, index)?;
+                    self.binary_op_operand(chunk, value, |a, b| a > b, Value::bool)?;
                 },
             }
+            self.trace_instruction(chunk)?;
+        }
+    }
+
+    fn trace_instruction(&mut self, chunk: &Chunk) -> InterpretResult<()> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace_instruction(chunk, self.instruction_offset, &self.stack)?;
+        }
+        Ok(())
+    }
+
+    /// Read a varint operand, advancing the instruction pointer.
+    /// Single byte operands are read inline as they're the most common.
+    fn read_varint(&self, code: &[u8], ip: &mut usize) -> InterpretResult<usize> {
+        match code.get(*ip) {
+            Some(&byte) if byte < 0x80 => {
+                *ip += 1;
+                Ok(byte as usize)
+            },
+            Some(_) => {
+                let mut operand = &code[*ip..];
+                match instructions::read_varint(&mut operand) {
+                    Ok(value) => {
+                        *ip = code.len() - operand.len();
+                        Ok(value as usize)
+                    },
+                    Err(err) => Err(instructions::operand_error(err, self.instruction_offset)),
+                }
+            },
+            None => Err(InterpretError::TruncatedInstruction { offset: self.instruction_offset }),
         }
     }
 
@@ -117,69 +235,339 @@ impl<'a> VirtualMachine<'a> {
         self.stack.push(value);
     }
 
-    fn pop(&mut self) -> Value {
-        self.stack.pop().unwrap()
+    fn pop(&mut self) -> InterpretResult<Value> {
+        let offset = self.instruction_offset;
+        match self.stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(InterpretError::StackUnderflow { offset }),
+        }
     }
 
-    fn peek(&self, distance: usize) -> &Value {
-        &self.stack[self.stack.len() - distance - 1]
+    fn add(&mut self, chunk: &Chunk) -> InterpretResult<()> {
+        let b = self.pop()?;
+        self.add_operand(chunk, b)
     }
 
-    fn binary_op<F, FC, T>(&mut self, binary_fn: F, value_creator: FC) -> InterpretResult<()>
-        where F: Fn(f64, f64) -> T, FC: Fn(T) -> Value
-    {
-        if !(self.peek(0).is_number() && self.peek(1).is_number()) {
-            return self.runtime_error("Operands must be numbers");
+    /// Add a right operand that isn't on the stack to the value on top
+    /// of it, so instructions with a constant operand don't need to push
+    /// it first and stay within their stack effect
+    fn add_operand(&mut self, chunk: &Chunk, b: Value) -> InterpretResult<()> {
+        let a = self.pop()?;
+        if a.is_string() && b.is_string() {
+            self.push(Value::string(format!("{}{}", a.as_string(), b.as_string())));
+        }
+        else if a.is_number() && b.is_number() {
+            self.push(Value::number(a.as_number() + b.as_number()));
+        }
+        else {
+            return self.runtime_error(chunk, "Operands must be two numbers or two strings");
         }
-        let b = self.pop();
-        let a = self.pop();
-        self.push(value_creator(binary_fn(a.as_number(), b.as_number())));
         Ok(())
     }
 
-    fn read_byte(&mut self) -> Option<OpCode> {
-        let mut opcode_byte = [0u8];
-        self.cursor.read(&mut opcode_byte).unwrap();
-        OpCode::from_byte(opcode_byte[0])
+    fn binary_op<F, FC, T>(&mut self, chunk: &Chunk, binary_fn: F, value_creator: FC) -> InterpretResult<()>
+        where F: Fn(f64, f64) -> T, FC: Fn(T) -> Value
+    {
+        let b = self.pop()?;
+        self.binary_op_operand(chunk, b, binary_fn, value_creator)
     }
 
-    fn read_constant(&mut self) -> Value {
-        let instruction = instructions::ConstantInstruction::parse(&mut self.cursor);
-        self.chunk.constants[instruction.constant_index as usize].clone()
+    /// Apply a binary operation to the value on top of the stack and
+    /// a right operand that isn't on the stack, as with `add_operand`
+    fn binary_op_operand<F, FC, T>(&mut self, chunk: &Chunk, b: Value, binary_fn: F, value_creator: FC)
+        -> InterpretResult<()>
+        where F: Fn(f64, f64) -> T, FC: Fn(T) -> Value
+    {
+        let a = self.pop()?;
+        if !(a.is_number() && b.is_number()) {
+            return self.runtime_error(chunk, "Operands must be numbers");
+        }
+        self.push(value_creator(binary_fn(a.as_number(), b.as_number())));
+        Ok(())
     }
 
-    fn read_constant_long(&mut self) -> Value {
-        let instruction = instructions::ConstantLongInstruction::parse(&mut self.cursor);
-        self.chunk.constants[instruction.constant_index as usize].clone()
+    fn constant(&self, chunk: &Chunk, index: usize) -> InterpretResult<Value> {
+        let offset = self.instruction_offset;
+        match chunk.constants.get(index) {
+            Some(value) => Ok(value.clone()),
+            None => Err(InterpretError::BadConstantIndex { offset, index }),
+        }
     }
 
     fn reset_stack(&mut self) {
         self.stack.clear();
     }
 
-    fn runtime_error(&mut self, message: &str) -> InterpretResult<()> {
-        let chunk_pos = self.cursor.position();
-        let line_number = self.chunk.lines.nth(chunk_pos as usize);
-        eprintln!("[line {}] {}", line_number, message);
-        self.reset_stack();
-        return Err(InterpretError::RuntimeError(message.to_string()));
+    fn runtime_error<T>(&self, chunk: &Chunk, message: &str) -> InterpretResult<T> {
+        let stack_trace = self.stack_trace(chunk);
+        Err(InterpretError::RuntimeError(RuntimeErrorDetails {
+            message: message.to_string(),
+            stack_trace,
+        }))
     }
+
+    /// Build a trace of the active frames, innermost first.
+    /// There are no function calls yet, so the top level script
+    /// is the only frame.
+    fn stack_trace(&self, chunk: &Chunk) -> Vec<StackTraceFrame> {
+        let line = chunk.lines.get(self.instruction_offset).cloned().unwrap_or(0);
+        vec![StackTraceFrame {
+            function_name: "script".to_string(),
+            source_file: self.source_file.clone(),
+            line,
+            // Columns aren't tracked in the chunk's line table
+            column: None,
+        }]
+    }
+}
+
+pub fn is_falsey(value: Value) -> bool {
+    value.is_nil() || (value.is_bool() && !value.as_bool())
 }
 
-fn is_falsey(value: Value) -> bool {
-    match value {
-        Value::NilValue => true,
-        Value::BoolValue(val) => !val,
-        _ => false,
+pub fn values_equal(left: Value, right: Value) -> bool {
+    if left.is_bool() && right.is_bool() {
+        left.as_bool() == right.as_bool()
+    } else if left.is_number() && right.is_number() {
+        left.as_number() == right.as_number()
+    } else if left.is_nil() && right.is_nil() {
+        true
+    } else if left.is_object() && right.is_object() {
+        object::objects_equal(left.as_object(), right.as_object())
+    } else {
+        false
     }
 }
 
-fn values_equal(left: Value, right: Value) -> bool {
-    match (left, right) {
-        (Value::BoolValue(left), Value::BoolValue(right)) => left == right,
-        (Value::NumberValue(left), Value::NumberValue(right)) => left == right,
-        (Value::NilValue, Value::NilValue) => true,
-        (Value::ObjValue(left), Value::ObjValue(right)) => object::objects_equal(&*left, &*right),
-        (_, _) => false,
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::compiler;
+    use ::instructions::{Instruction, OpCode};
+    use ::register_compiler;
+    use ::register_machine::RegisterMachine;
+
+    /// Tests that aren't specific to the stack machine are also run
+    /// on the register machine, with the chunk translated for it
+    #[derive(Debug,Copy,Clone)]
+    enum Backend {
+        Stack,
+        Register,
+    }
+
+    const BACKENDS: [Backend; 2] = [Backend::Stack, Backend::Register];
+
+    fn interpret_with(backend: Backend, chunk: &Chunk, source_file: Option<&str>) -> InterpretResult<Value> {
+        match backend {
+            Backend::Stack => {
+                let mut vm = VirtualMachine::new();
+                vm.set_source_file(source_file);
+                vm.interpret(chunk)
+            },
+            Backend::Register => {
+                let register_chunk = register_compiler::translate(chunk)?;
+                let mut machine = RegisterMachine::new();
+                machine.set_source_file(source_file);
+                machine.interpret(&register_chunk)
+            },
+        }
+    }
+
+    #[test]
+    fn test_runtime_error_has_stack_trace() {
+        let chunk = compiler::compile("1 +\n-true").unwrap();
+
+        for &backend in BACKENDS.iter() {
+            match interpret_with(backend, &chunk, Some("test.lox")) {
+                Err(InterpretError::RuntimeError(details)) => {
+                    assert_eq!(details.message, "Operand must be a number");
+                    assert_eq!(details.stack_trace, vec![StackTraceFrame {
+                        function_name: "script".to_string(),
+                        source_file: Some("test.lox".to_string()),
+                        line: 2,
+                        column: None,
+                    }]);
+                },
+                _ => {
+                    assert!(false, "Expected runtime error with {:?} backend", backend);
+                }
+            }
+        }
+    }
+
+    /// Run raw code on each backend, returning the result from each
+    fn run_code(code: Vec<u8>, constants: Vec<Value>) -> Vec<(Backend, InterpretResult<Value>)> {
+        let mut chunk = Chunk::new();
+        for _ in 0..code.len() {
+            chunk.lines.push(1);
+        }
+        chunk.code = code;
+        chunk.constants = constants;
+        BACKENDS.iter().map(|&backend| (backend, interpret_with(backend, &chunk, None))).collect()
+    }
+
+    #[test]
+    fn test_interpret_returns_value() {
+        let chunk = compiler::compile("1 + 2").unwrap();
+
+        for &backend in BACKENDS.iter() {
+            let value = interpret_with(backend, &chunk, None).unwrap();
+            assert_eq!(value.as_number(), 3.0);
+        }
+    }
+
+    #[test]
+    fn test_stack_is_preallocated() {
+        let chunk = compiler::compile_unoptimised("1 + (2 + (3 + 4))").unwrap();
+        let mut vm = VirtualMachine::new();
+
+        vm.interpret(&chunk).unwrap();
+        assert!(vm.stack.capacity() >= 4);
+    }
+
+    #[test]
+    fn test_superinstructions_stay_within_max_stack_depth() {
+        // Operations on literals are folded away, so only one that
+        // fails at runtime is left as a superinstruction
+        let chunk = compiler::compile("true + 1").unwrap();
+        assert_eq!(chunk.decoded_instructions(), vec![
+            Instruction::True, Instruction::AddConstant(0), Instruction::Return]);
+        assert_eq!(chunk.max_stack_depth, 1);
+        let mut vm = VirtualMachine::new();
+
+        assert!(vm.interpret(&chunk).is_err(), "Expected runtime error");
+        // The stack would have grown past its preallocated size if
+        // the constant operand had been pushed
+        assert_eq!(vm.stack.capacity(), 1);
+    }
+
+    #[test]
+    fn test_stack_limit() {
+        let chunk = compiler::compile_unoptimised("1 + (2 + (3 + 4))").unwrap();
+        let mut vm = VirtualMachine::new();
+        vm.set_stack_limit(3);
+
+        match vm.interpret(&chunk) {
+            Err(InterpretError::StackLimitExceeded { depth: 4, limit: 3 }) => {},
+            result => assert!(false, "Expected stack limit error, got {:?}", result),
+        }
+        vm.set_stack_limit(4);
+        assert_eq!(vm.interpret(&chunk).unwrap().as_number(), 10.0);
+    }
+
+    #[test]
+    fn test_vm_can_be_reused_after_error() {
+        let mut vm = VirtualMachine::new();
+        let bad_chunk = compiler::compile("1 + true").unwrap();
+        let good_chunk = compiler::compile("\"a\" + \"b\"").unwrap();
+
+        assert!(vm.interpret(&bad_chunk).is_err(), "Expected runtime error");
+        let value = vm.interpret(&good_chunk).unwrap();
+        assert_eq!(value.as_string(), "ab");
+        assert_eq!(vm.stack.len(), 0);
+    }
+
+    fn assert_bool(source: &str, expected: bool) {
+        let chunk = compiler::compile_unoptimised(source).unwrap();
+        for &backend in BACKENDS.iter() {
+            let value = interpret_with(backend, &chunk, None).unwrap();
+            assert_eq!(value.as_bool(), expected, "Unexpected result for {} with {:?} backend", source, backend);
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_bool("1 <= 1", true);
+        assert_bool("2 <= 1", false);
+        assert_bool("1 >= 1", true);
+        assert_bool("1 >= 2", false);
+        assert_bool("1 != 2", true);
+        assert_bool("\"a\" != \"a\"", false);
+        assert_bool("nil != false", true);
+    }
+
+    #[test]
+    fn test_comparisons_with_nan() {
+        for operator in &["<", "<=", ">", ">=", "=="] {
+            assert_bool(&format!("0 / 0 {} 1", operator), false);
+            assert_bool(&format!("1 {} 0 / 0", operator), false);
+            assert_bool(&format!("0 / 0 {} 0 / 0", operator), false);
+        }
+        assert_bool("0 / 0 != 0 / 0", true);
+        assert_bool("0 / 0 != 1", true);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        for (backend, result) in run_code(vec![OpCode::Negate.as_byte(), OpCode::Return.as_byte()], vec![]) {
+            match result {
+                Err(InterpretError::StackUnderflow { offset: 0 }) => {},
+                _ => assert!(false, "Expected stack underflow, got {:?} with {:?} backend", result, backend),
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_constant() {
+        for (backend, result) in run_code(vec![OpCode::True.as_byte(), OpCode::Constant.as_byte(), 0x80u8], vec![]) {
+            match result {
+                Err(InterpretError::TruncatedInstruction { offset: 1 }) => {},
+                _ => assert!(false, "Expected truncated instruction, got {:?} with {:?} backend", result, backend),
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_byte_constant_operands() {
+        let mut constants = vec![Value::nil(); 300];
+        constants[299] = Value::number(2.0);
+        let results = run_code(vec![
+            OpCode::Constant.as_byte(), 0xab, 0x02,
+            OpCode::AddConstant.as_byte(), 0xab, 0x02,
+            OpCode::Return.as_byte(),
+        ], constants);
+        for (_, result) in results {
+            assert_eq!(result.unwrap().as_number(), 4.0);
+        }
+    }
+
+    #[test]
+    fn test_malformed_operand() {
+        for (backend, result) in run_code(vec![OpCode::Constant.as_byte(), 0xff, 0xff, 0xff, 0xff, 0x7f], vec![]) {
+            match result {
+                Err(InterpretError::MalformedOperand { offset: 0 }) => {},
+                _ => assert!(false, "Expected malformed operand, got {:?} with {:?} backend", result, backend),
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_return() {
+        for (backend, result) in run_code(vec![OpCode::True.as_byte()], vec![]) {
+            match result {
+                Err(InterpretError::TruncatedInstruction { offset: 1 }) => {},
+                _ => assert!(false, "Expected truncated instruction, got {:?} with {:?} backend", result, backend),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bad_constant_index() {
+        for (backend, result) in run_code(vec![OpCode::Constant.as_byte(), 1u8, OpCode::Return.as_byte()], vec![Value::nil()]) {
+            match result {
+                Err(InterpretError::BadConstantIndex { offset: 0, index: 1 }) => {},
+                _ => assert!(false, "Expected bad constant index, got {:?} with {:?} backend", result, backend),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_op_code() {
+        for (backend, result) in run_code(vec![OpCode::Nil.as_byte(), 255u8], vec![]) {
+            match result {
+                Err(InterpretError::UnknownOpCode { offset: 1, byte: 255 }) => {},
+                _ => assert!(false, "Expected unknown op code, got {:?} with {:?} backend", result, backend),
+            }
+        }
     }
 }